@@ -68,7 +68,7 @@ func (g Gtm) ServeDNS(ctx context.Context, w dns.ResponseWriter, r *dns.Msg) (in
 
 	// req.Answer = []dns.RR{rr}
 
-	url := fmt.Sprintf("http://127.0.0.1:8080/info?name=%s", question)
+	url := fmt.Sprintf("http://127.0.0.1:8080/info?name=%s&family=ipv4", question)
 	resp, err := http.Get(url)
 	if err != nil {
 		log.Error("Call to healthchecker failed.")
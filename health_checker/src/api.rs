@@ -0,0 +1,785 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::auth::{self, Auth, HmacKeyConfig, TokenConfig};
+use crate::events::{Event, Events};
+use crate::healthcheck::{
+    unix_time, AddressFamily, AdminMode, AdminState, HealthTable, Member, MemberConfig, Pool,
+    PoolHealth, DEFAULT_WEIGHT,
+};
+use crate::metrics::Metrics;
+use crate::selection::{self, LbMethod};
+use crate::supervisor::{Change, ChangeError, ReloadReport, SupervisorHandle};
+use axum::{
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        FromRef, Path, Query, State,
+    },
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+#[derive(Clone, Default, Deserialize)]
+///Configuration for the administrative API
+pub struct ApiOptions {
+    /// Bearer tokens, each with a role. The admin routes are disabled without any credentials.
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    /// Keys for HMAC signed requests, each with a role
+    #[serde(default)]
+    pub hmac_keys: Vec<HmacKeyConfig>,
+    /// Write pool changes made through the API back to the config file
+    #[serde(default)]
+    pub write_config: bool,
+}
+
+/// TTL reported with lookup answers, which is the TTL the DNS server answers with
+#[derive(Clone, Copy)]
+pub struct Ttl(pub u32);
+
+/// Routes of the versioned JSON API. Nest them under `/api/v1`.
+/// Lookups are open, reading pools needs the read-only role once any credentials are
+/// configured, and changes always need the admin role.
+pub fn routes<S>(auth: Auth) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    HealthTable: FromRef<S>,
+    Metrics: FromRef<S>,
+    Ttl: FromRef<S>,
+    Events: FromRef<S>,
+    SupervisorHandle: FromRef<S>,
+{
+    let read = || middleware::from_fn_with_state(auth.clone(), auth::require_read);
+    let admin = || middleware::from_fn_with_state(auth.clone(), auth::require_admin);
+    Router::new()
+        .route("/pools", get(list_pools).route_layer(read()))
+        .route(
+            "/pools/:name",
+            get(get_pool)
+                .route_layer(read())
+                .merge(put(put_pool).delete(delete_pool).route_layer(admin())),
+        )
+        .route("/pools/:name/lookup", get(lookup))
+        .route(
+            "/pools/:name/members/:host",
+            put(put_member).delete(delete_member).route_layer(admin()),
+        )
+        .route(
+            "/pools/:name/members/:host/admin",
+            put(put_admin_state).route_layer(admin()),
+        )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Machine readable error codes
+pub enum ErrorCode {
+    /// The query string or body couldn't be parsed
+    InvalidRequest,
+    /// Missing or invalid credentials
+    Unauthorized,
+    /// The credentials don't allow the request
+    Forbidden,
+    PoolNotFound,
+    MemberNotFound,
+    /// The change would leave an invalid config
+    InvalidConfig,
+    /// The change was applied but couldn't be written to the config file
+    ConfigWriteFailed,
+    /// The health checker is shutting down
+    Unavailable,
+    /// No member is up and some haven't been probed yet. Try again shortly.
+    HealthUnknown,
+    /// No member is up and the pool has no fallback IP
+    NoHealthyMembers,
+    InternalError,
+}
+
+/// Error response. Serialized as `{"error": {"code": ..., "message": ...}}`.
+#[derive(Debug, PartialEq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: ErrorCode, message: &str) -> ApiError {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn pool_not_found() -> ApiError {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            ErrorCode::PoolNotFound,
+            "Pool not found",
+        )
+    }
+
+    fn invalid_request(message: &str) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, message)
+    }
+
+    fn internal() -> ApiError {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
+            "Internal Server Error",
+        )
+    }
+}
+
+impl From<ChangeError> for ApiError {
+    fn from(e: ChangeError) -> ApiError {
+        let (status, code) = match &e {
+            ChangeError::PoolNotFound => (StatusCode::NOT_FOUND, ErrorCode::PoolNotFound),
+            ChangeError::MemberNotFound => (StatusCode::NOT_FOUND, ErrorCode::MemberNotFound),
+            ChangeError::Invalid(_) => (StatusCode::BAD_REQUEST, ErrorCode::InvalidConfig),
+            ChangeError::WriteFailed(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::ConfigWriteFailed,
+            ),
+            ChangeError::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, ErrorCode::Unavailable),
+        };
+        ApiError::new(status, code, &e.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: ErrorCode,
+    message: &'a str,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": ErrorBody {
+            code: self.code,
+            message: &self.message,
+        }});
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// Overview of a pool in the pool list
+pub struct PoolSummary {
+    pub name: String,
+    pub lb_method: LbMethod,
+    /// Members, not counting the fallback
+    pub members: usize,
+    /// Members which are up
+    pub healthy: usize,
+    pub health_unknown: bool,
+}
+
+#[derive(Serialize)]
+/// A pool and the state of each of its members
+pub struct PoolDetail {
+    pub name: String,
+    pub lb_method: LbMethod,
+    pub health_unknown: bool,
+    /// The pool's members, with the fallback, if any, last
+    pub members: Vec<Member>,
+}
+
+#[derive(Deserialize)]
+pub struct LookupParams {
+    /// Only consider members with an address of this family. Any address by default.
+    family: Option<AddressFamily>,
+    /// Load balancing method to use instead of the pool's own
+    method: Option<LbMethod>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// Answer to a lookup
+pub struct LookupResponse {
+    pub pool: String,
+    /// Address chosen for this lookup
+    pub address: IpAddr,
+    /// Host of the chosen member
+    pub host: String,
+    /// Every address the lookup could have chosen from
+    pub candidates: Vec<IpAddr>,
+    /// Load balancing method used to choose the address
+    pub method: LbMethod,
+    pub family: AddressFamily,
+    pub ttl: u32,
+    /// Whether no member was healthy and the fallback IP was served instead
+    pub fallback_used: bool,
+}
+
+fn summary(name: &str, pool: &PoolHealth) -> PoolSummary {
+    let members = pool.members.iter().filter(|m| !m.is_fallback());
+    PoolSummary {
+        name: name.to_string(),
+        lb_method: pool.lb_method,
+        members: members.clone().count(),
+        healthy: members.filter(|m| m.is_up()).count(),
+        health_unknown: pool.health_unknown(),
+    }
+}
+
+/// List every pool, sorted by name
+pub async fn list_pools(
+    State(state): State<HealthTable>,
+) -> Result<Json<Vec<PoolSummary>>, ApiError> {
+    let map = state.lock().map_err(|_| ApiError::internal())?;
+    let mut pools: Vec<PoolSummary> = map.iter().map(|(n, p)| summary(n, p)).collect();
+    pools.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(pools))
+}
+
+/// Show a single pool and its members
+pub async fn get_pool(
+    Path(name): Path<String>,
+    State(state): State<HealthTable>,
+) -> Result<Json<PoolDetail>, ApiError> {
+    let map = state.lock().map_err(|_| ApiError::internal())?;
+    let pool = map.get(&name).ok_or_else(ApiError::pool_not_found)?;
+    Ok(Json(PoolDetail {
+        lb_method: pool.lb_method,
+        health_unknown: pool.health_unknown(),
+        members: pool.members.clone(),
+        name,
+    }))
+}
+
+/// Choose an address of the pool, the same way `/info` and the DNS server do unless another
+/// method is requested
+pub async fn lookup(
+    Path(name): Path<String>,
+    params: Result<Query<LookupParams>, QueryRejection>,
+    State(state): State<HealthTable>,
+    State(metrics): State<Metrics>,
+    State(Ttl(ttl)): State<Ttl>,
+) -> Result<Json<LookupResponse>, ApiError> {
+    let Query(params) = params.map_err(|e| ApiError::invalid_request(&e.body_text()))?;
+    let mut map = state.lock().map_err(|_| ApiError::internal())?;
+    let pool = map.get_mut(&name).ok_or_else(ApiError::pool_not_found)?;
+    let family = params.family.unwrap_or(AddressFamily::Any);
+    let method = params.method.unwrap_or(pool.lb_method);
+
+    let unknown = pool.health_unknown();
+    let candidates: Vec<IpAddr> = selection::active(pool, family)
+        .iter()
+        .map(|m| m.ip)
+        .collect();
+    let member = selection::select_with(pool, method, family);
+    metrics.lookup(&name, "api", member.is_some_and(|m| m.is_fallback()));
+
+    match member {
+        Some(m) => Ok(Json(LookupResponse {
+            address: m.ip,
+            host: m.host.clone(),
+            fallback_used: m.is_fallback(),
+            pool: name,
+            candidates,
+            method,
+            family,
+            ttl,
+        })),
+        None if unknown => Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::HealthUnknown,
+            "Pool health unknown",
+        )),
+        None => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NoHealthyMembers,
+            "No healthy members and no fallback IP",
+        )),
+    }
+}
+
+#[derive(Default, Deserialize)]
+/// Settings of a member added or updated through the API. The host is taken from the path.
+pub struct MemberBody {
+    weight: Option<u32>,
+    #[serde(default)]
+    priority: u32,
+}
+
+/// Add a pool, or replace the pool of the same name. The body is the pool's config, as in the
+/// config file. The name may be left out of the body, but has to match the path if it's given.
+pub async fn put_pool(
+    Path(name): Path<String>,
+    State(supervisor): State<SupervisorHandle>,
+    body: Result<Json<serde_json::Value>, JsonRejection>,
+) -> Result<Json<ReloadReport>, ApiError> {
+    let Json(mut body) = body.map_err(|e| ApiError::invalid_request(&e.body_text()))?;
+    let fields = body
+        .as_object_mut()
+        .ok_or_else(|| ApiError::invalid_request("The pool must be a JSON object"))?;
+    match fields.get("name").and_then(|n| n.as_str()) {
+        Some(n) if n != name => {
+            return Err(ApiError::invalid_request(
+                "The pool name doesn't match the path",
+            ))
+        }
+        _ => {
+            fields.insert("name".into(), name.into());
+        }
+    }
+    let pool: Pool = serde_json::from_value(body)
+        .map_err(|e| ApiError::invalid_request(&format!("Invalid pool: {e}")))?;
+    Ok(Json(supervisor.change(Change::PutPool(pool)).await?))
+}
+
+/// Remove a pool and stop its pollers
+pub async fn delete_pool(
+    Path(name): Path<String>,
+    State(supervisor): State<SupervisorHandle>,
+) -> Result<Json<ReloadReport>, ApiError> {
+    Ok(Json(supervisor.change(Change::DeletePool(name)).await?))
+}
+
+/// Add a member to a pool, or update its weight and priority group
+pub async fn put_member(
+    Path((pool, host)): Path<(String, String)>,
+    State(supervisor): State<SupervisorHandle>,
+    body: Result<Json<MemberBody>, JsonRejection>,
+) -> Result<Json<ReloadReport>, ApiError> {
+    // The body is optional
+    let body = match body {
+        Ok(Json(b)) => b,
+        Err(JsonRejection::MissingJsonContentType(_)) => MemberBody::default(),
+        Err(e) => return Err(ApiError::invalid_request(&e.body_text())),
+    };
+    let member = MemberConfig {
+        weight: body.weight.unwrap_or(DEFAULT_WEIGHT),
+        priority: body.priority,
+        host,
+    };
+    Ok(Json(
+        supervisor
+            .change(Change::PutMember { pool, member })
+            .await?,
+    ))
+}
+
+/// Remove a member from a pool and stop its poller
+pub async fn delete_member(
+    Path((pool, host)): Path<(String, String)>,
+    State(supervisor): State<SupervisorHandle>,
+) -> Result<Json<ReloadReport>, ApiError> {
+    Ok(Json(
+        supervisor
+            .change(Change::DeleteMember { pool, host })
+            .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+/// New administrative state of a member
+pub struct AdminBody {
+    state: AdminMode,
+    reason: Option<String>,
+    /// Unix time, in seconds, after which the member is enabled again
+    until: Option<u64>,
+    /// Seconds after which the member is enabled again. An alternative to `until`.
+    duration: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AdminResponse {
+    pub pool: String,
+    pub host: String,
+    pub admin: AdminState,
+}
+
+/// Enable, disable or force a member offline. The state is kept across reloads, applies to
+/// every address of the member, and goes back to enabled once it expires.
+pub async fn put_admin_state(
+    Path((pool, host)): Path<(String, String)>,
+    State(state): State<HealthTable>,
+    State(events): State<Events>,
+    body: Result<Json<AdminBody>, JsonRejection>,
+) -> Result<Json<AdminResponse>, ApiError> {
+    let Json(body) = body.map_err(|e| ApiError::invalid_request(&e.body_text()))?;
+    let now = unix_time().unwrap_or_default();
+    let until = match (body.until, body.duration) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::invalid_request(
+                "Only one of until and duration can be given",
+            ))
+        }
+        // A state which has already run out would be enabled again straight away
+        (Some(until), None) if until <= now => {
+            return Err(ApiError::invalid_request("until is in the past"))
+        }
+        (Some(until), None) => Some(until),
+        (None, Some(0)) => return Err(ApiError::invalid_request("duration must be at least 1")),
+        (None, Some(duration)) => Some(
+            now.checked_add(duration)
+                .ok_or_else(|| ApiError::invalid_request("duration is too long"))?,
+        ),
+        (None, None) => None,
+    };
+    let admin = match body.state {
+        // Enabling a member clears the reason and expiry along with the old state
+        AdminMode::Enabled => AdminState::default(),
+        mode => AdminState {
+            state: mode,
+            reason: body.reason,
+            until,
+        },
+    };
+
+    let mut map = state.lock().map_err(|_| ApiError::internal())?;
+    let members = &mut map
+        .get_mut(&pool)
+        .ok_or_else(ApiError::pool_not_found)?
+        .members;
+    let mut found = false;
+    for m in members
+        .iter_mut()
+        .filter(|m| !m.is_fallback() && m.host == host)
+    {
+        m.admin = admin.clone();
+        found = true;
+    }
+    if !found {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            ErrorCode::MemberNotFound,
+            "Member not found",
+        ));
+    }
+    log::info!(
+        "Host: {} admin state set to {:?} for {} ({})",
+        host,
+        admin.state,
+        pool,
+        admin.reason.as_deref().unwrap_or("no reason given")
+    );
+    drop(map);
+    events.send(Event::Admin {
+        pool: pool.clone(),
+        host: host.clone(),
+        admin: admin.clone(),
+    });
+    Ok(Json(AdminResponse { pool, host, admin }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::healthcheck::Health;
+    use crate::supervisor::Supervisor;
+    use crate::test_util::{member, table};
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn get(
+        cache: &HealthTable,
+        name: &str,
+        method: Option<LbMethod>,
+    ) -> Result<LookupResponse, ApiError> {
+        let params = LookupParams {
+            family: None,
+            method,
+        };
+        lookup(
+            Path(name.into()),
+            Ok(Query(params)),
+            State(Arc::clone(cache)),
+            State(Metrics::new()),
+            State(Ttl(5)),
+        )
+        .await
+        .map(|Json(r)| r)
+    }
+
+    #[tokio::test]
+    async fn lookup_reports_choice_and_candidates() {
+        let cache = table(
+            "app",
+            vec![
+                member("a", [10, 0, 0, 1], Health::Down),
+                member("b", [10, 0, 0, 2], Health::Up),
+                member("c", [10, 0, 0, 3], Health::Up),
+                Member::fallback([10, 0, 0, 9].into()),
+            ],
+        );
+
+        let answer = get(&cache, "app", None).await.unwrap();
+        assert_eq!(answer.address, IpAddr::from([10, 0, 0, 2]));
+        assert_eq!(answer.host, "b");
+        assert_eq!(
+            answer.candidates,
+            vec![IpAddr::from([10, 0, 0, 2]), IpAddr::from([10, 0, 0, 3])]
+        );
+        assert_eq!(answer.method, LbMethod::Priority);
+        assert_eq!(answer.ttl, 5);
+        assert!(!answer.fallback_used);
+
+        // The requested method overrides the pool's
+        let first = get(&cache, "app", Some(LbMethod::RoundRobin))
+            .await
+            .unwrap();
+        let second = get(&cache, "app", Some(LbMethod::RoundRobin))
+            .await
+            .unwrap();
+        assert_ne!(first.address, second.address);
+        assert_eq!(first.method, LbMethod::RoundRobin);
+    }
+
+    #[tokio::test]
+    async fn lookup_errors_have_codes() {
+        let err = get(&table("app", vec![]), "nope", None).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::PoolNotFound);
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+
+        let cache = table("app", vec![member("a", [10, 0, 0, 1], Health::Unknown)]);
+        let err = get(&cache, "app", None).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::HealthUnknown);
+        assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+
+        let cache = table("app", vec![member("a", [10, 0, 0, 1], Health::Down)]);
+        let err = get(&cache, "app", None).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NoHealthyMembers);
+
+        // The fallback is served when nothing else is healthy
+        let cache = table(
+            "app",
+            vec![
+                member("a", [10, 0, 0, 1], Health::Down),
+                Member::fallback([10, 0, 0, 9].into()),
+            ],
+        );
+        let answer = get(&cache, "app", None).await.unwrap();
+        assert!(answer.fallback_used);
+        assert_eq!(answer.candidates, vec![IpAddr::from([10, 0, 0, 9])]);
+    }
+
+    #[tokio::test]
+    async fn lists_pools() {
+        let cache = table(
+            "app",
+            vec![
+                member("a", [10, 0, 0, 1], Health::Down),
+                member("b", [10, 0, 0, 2], Health::Up),
+                Member::fallback([10, 0, 0, 9].into()),
+            ],
+        );
+        let Json(pools) = list_pools(State(cache)).await.unwrap();
+        assert_eq!(
+            pools,
+            vec![PoolSummary {
+                name: "app".into(),
+                lb_method: LbMethod::Priority,
+                members: 2,
+                healthy: 1,
+                health_unknown: false,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_state_takes_members_out_of_lookups() {
+        let cache = table(
+            "app",
+            vec![
+                member("a", [10, 0, 0, 1], Health::Up),
+                member("b", [10, 0, 0, 2], Health::Up),
+            ],
+        );
+        let put = |host: &str, body: serde_json::Value| {
+            put_admin_state(
+                Path(("app".into(), host.into())),
+                State(Arc::clone(&cache)),
+                State(Events::new()),
+                Ok(Json(serde_json::from_value(body).unwrap())),
+            )
+        };
+
+        let Json(res) = put(
+            "a",
+            serde_json::json!({"state": "disabled", "reason": "patching"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.admin.state, AdminMode::Disabled);
+        assert_eq!(res.admin.reason.as_deref(), Some("patching"));
+        let r = get(&cache, "app", None).await.unwrap();
+        assert_eq!(r.host, "b");
+        assert_eq!(r.candidates.len(), 1);
+
+        let err = put(
+            "b",
+            serde_json::json!({"state": "forced_offline", "until": 1, "duration": 60}),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidRequest);
+        let err = put("c", serde_json::json!({"state": "disabled"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::MemberNotFound);
+
+        // Enabling clears the reason
+        let Json(res) = put(
+            "a",
+            serde_json::json!({"state": "enabled", "reason": "done"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.admin, AdminState::default());
+        assert_eq!(get(&cache, "app", None).await.unwrap().candidates.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn admin_state_expiry_must_be_in_the_future() {
+        let cache = table("app", vec![member("a", [10, 0, 0, 1], Health::Up)]);
+        for body in [
+            serde_json::json!({"state": "disabled", "duration": u64::MAX}),
+            serde_json::json!({"state": "disabled", "duration": 0}),
+            serde_json::json!({"state": "disabled", "until": 1}),
+        ] {
+            let err = put_admin_state(
+                Path(("app".into(), "a".into())),
+                State(Arc::clone(&cache)),
+                State(Events::new()),
+                Ok(Json(serde_json::from_value(body).unwrap())),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidRequest);
+        }
+        assert!(cache.lock().unwrap()["app"].members[0].is_enabled());
+    }
+
+    #[derive(Clone)]
+    struct TestState {
+        cache: HealthTable,
+        supervisor: SupervisorHandle,
+    }
+
+    impl FromRef<TestState> for HealthTable {
+        fn from_ref(state: &TestState) -> Self {
+            Arc::clone(&state.cache)
+        }
+    }
+
+    impl FromRef<TestState> for Metrics {
+        fn from_ref(_: &TestState) -> Self {
+            Metrics::new()
+        }
+    }
+
+    impl FromRef<TestState> for Ttl {
+        fn from_ref(_: &TestState) -> Self {
+            Ttl(5)
+        }
+    }
+
+    impl FromRef<TestState> for Events {
+        fn from_ref(_: &TestState) -> Self {
+            Events::new()
+        }
+    }
+
+    impl FromRef<TestState> for SupervisorHandle {
+        fn from_ref(state: &TestState) -> Self {
+            state.supervisor.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_check_roles() {
+        let options: ApiOptions = serde_json::from_value(serde_json::json!({
+            "tokens": ["s3cret", {"token": "viewer", "role": "read_only"}],
+        }))
+        .unwrap();
+        // No supervisor is running, so changes which get past the checks are unavailable
+        let (supervisor, _) = Supervisor::channel();
+        let app = Router::new()
+            .nest("/api/v1", routes(Auth::new(&options)))
+            .with_state(TestState {
+                cache: table("app", vec![member("a", [10, 0, 0, 1], Health::Up)]),
+                supervisor,
+            });
+
+        let json = "application/json";
+        for (method, path, token, expected) in [
+            ("GET", "/pools/app/lookup", None, StatusCode::OK),
+            ("GET", "/pools", None, StatusCode::UNAUTHORIZED),
+            ("GET", "/pools/app", None, StatusCode::UNAUTHORIZED),
+            ("GET", "/pools/app", Some("viewer"), StatusCode::OK),
+            ("PUT", "/pools/app", None, StatusCode::UNAUTHORIZED),
+            ("PUT", "/pools/app", Some("viewer"), StatusCode::FORBIDDEN),
+            (
+                "DELETE",
+                "/pools/app",
+                Some("viewer"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "PUT",
+                "/pools/app/members/b",
+                Some("viewer"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "DELETE",
+                "/pools/app/members/a",
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "PUT",
+                "/pools/app/members/a/admin",
+                Some("viewer"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "PUT",
+                "/pools/app/members/a/admin",
+                Some("s3cret"),
+                StatusCode::OK,
+            ),
+            (
+                "DELETE",
+                "/pools/app",
+                Some("s3cret"),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+        ] {
+            let mut req = Request::builder()
+                .method(method)
+                .uri(format!("/api/v1{path}"))
+                .header(header::CONTENT_TYPE, json);
+            if let Some(token) = token {
+                req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            let body = Body::from(r#"{"state": "disabled"}"#);
+            let res = app.clone().oneshot(req.body(body).unwrap()).await.unwrap();
+            assert_eq!(res.status(), expected, "{method} {path} with {token:?}");
+        }
+    }
+}
@@ -0,0 +1,432 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::{ApiError, ApiOptions, ErrorCode};
+use crate::healthcheck::unix_time;
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, OriginalUri, State},
+    http::{header, Method, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ring::{digest, hmac};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// How far, in seconds, the timestamp of a signed request may be from the current time
+const MAX_CLOCK_SKEW: u64 = 300;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+/// What a set of credentials may do. Each role may do everything the ones before it can.
+pub enum Role {
+    /// Read pools, `/dump`, `/metrics` and `/events`
+    ReadOnly,
+    /// Also change pools and members, and reload the config
+    Admin,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum TokenConfigRepr {
+    Token(String),
+    WithRole { token: String, role: Role },
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(from = "TokenConfigRepr")]
+///Configuration of a bearer token. Accepts either a plain token, which has the admin role, or an
+///object with a `token` and a `role`.
+pub struct TokenConfig {
+    pub token: String,
+    pub role: Role,
+}
+impl From<TokenConfigRepr> for TokenConfig {
+    fn from(repr: TokenConfigRepr) -> TokenConfig {
+        match repr {
+            TokenConfigRepr::Token(token) => TokenConfig {
+                token,
+                role: Role::Admin,
+            },
+            TokenConfigRepr::WithRole { token, role } => TokenConfig { token, role },
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+///Configuration of a key for HMAC signed requests
+pub struct HmacKeyConfig {
+    /// Sent with each request to say which key signed it
+    pub id: String,
+    pub secret: String,
+    pub role: Role,
+}
+
+impl ApiOptions {
+    /// Check the credentials, which parse fine but can't work
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.tokens.iter().any(|t| t.token.is_empty()) {
+            return Err("api: tokens can't be empty".into());
+        }
+        let mut ids = HashSet::new();
+        for key in &self.hmac_keys {
+            if key.secret.is_empty() {
+                return Err(format!("api: HMAC key {} has an empty secret", key.id).into());
+            }
+            if !ids.insert(key.id.as_str()) {
+                return Err(format!("api: duplicate HMAC key {}", key.id).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Credentials {
+    tokens: Vec<TokenConfig>,
+    /// Key id, key and role
+    keys: Vec<(String, hmac::Key, Role)>,
+    /// Signatures accepted within the clock skew window, with their timestamps. A signature
+    /// covers the whole request, so seeing one again means the request was replayed.
+    seen: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+/// Bearer tokens and HMAC keys accepted by the protected routes
+#[derive(Clone)]
+pub struct Auth(Arc<Credentials>);
+
+impl Default for Auth {
+    fn default() -> Auth {
+        Auth::new(&ApiOptions::default())
+    }
+}
+
+impl Auth {
+    pub fn new(options: &ApiOptions) -> Auth {
+        let keys = options
+            .hmac_keys
+            .iter()
+            .map(|k| {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, k.secret.as_bytes());
+                (k.id.clone(), key, k.role)
+            })
+            .collect();
+        Auth(Arc::new(Credentials {
+            tokens: options.tokens.clone(),
+            keys,
+            seen: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.tokens.is_empty() && self.0.keys.is_empty()
+    }
+
+    /// Role of a bearer token
+    fn bearer(&self, token: &str) -> Option<Role> {
+        self.0
+            .tokens
+            .iter()
+            .find(|known| same_secret(&known.token, token))
+            .map(|known| known.role)
+    }
+
+    /// Role of the key which signed the request. `signature` is the part of the authorization
+    /// header after `HMAC `: the key id, the Unix time of the request in seconds and the hex
+    /// encoded signature, separated by colons.
+    fn signed(
+        &self,
+        signature: &str,
+        method: &Method,
+        uri: &Uri,
+        body: &[u8],
+        now: u64,
+    ) -> Option<Role> {
+        let mut parts = signature.splitn(3, ':');
+        let (id, timestamp, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        let timestamp: u64 = timestamp.parse().ok()?;
+        if timestamp.abs_diff(now) > MAX_CLOCK_SKEW {
+            return None;
+        }
+        let (_, key, role) = self.0.keys.iter().find(|(known, _, _)| known == id)?;
+        let message = signed_message(method, uri, timestamp, body);
+        let signature = from_hex(signature)?;
+        hmac::verify(key, message.as_bytes(), &signature).ok()?;
+
+        let mut seen = self.0.seen.lock().unwrap();
+        // Signatures which have left the window would be rejected for their timestamp anyway
+        seen.retain(|_, t| t.abs_diff(now) <= MAX_CLOCK_SKEW);
+        if seen.insert(signature, timestamp).is_some() {
+            return None;
+        }
+        Some(*role)
+    }
+
+    /// Check the request's credentials allow `required`. The body is only read when the
+    /// request is signed, and is put back for the handler.
+    async fn authorize(
+        &self,
+        required: Role,
+        req: Request<Body>,
+    ) -> Result<Request<Body>, ApiError> {
+        if self.is_empty() {
+            // Without credentials the read routes are open and the rest are off
+            if required == Role::ReadOnly {
+                return Ok(req);
+            }
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                ErrorCode::Forbidden,
+                "The admin API is disabled. Configure api.tokens or api.hmac_keys to enable it.",
+            ));
+        }
+
+        let auth = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .map(String::from)
+            .unwrap_or_default();
+        let (req, role) = if let Some(token) = auth.strip_prefix("Bearer ") {
+            let role = self.bearer(token);
+            (req, role)
+        } else if let Some(signature) = auth.strip_prefix("HMAC ") {
+            let (parts, body) = req.into_parts();
+            let body = Bytes::from_request(Request::new(body), &())
+                .await
+                .map_err(|e| {
+                    ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        ErrorCode::InvalidRequest,
+                        &e.body_text(),
+                    )
+                })?;
+            // Signatures cover the path as sent, before any nesting stripped it
+            let uri = parts
+                .extensions
+                .get::<OriginalUri>()
+                .map_or(&parts.uri, |o| &o.0);
+            let now = unix_time().unwrap_or_default();
+            let role = self.signed(signature, &parts.method, uri, &body, now);
+            (Request::from_parts(parts, Body::from(body)), role)
+        } else {
+            (req, None)
+        };
+
+        match role {
+            Some(role) if role >= required => Ok(req),
+            Some(_) => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                ErrorCode::Forbidden,
+                "These credentials can't make changes",
+            )),
+            None => Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                ErrorCode::Unauthorized,
+                "Missing or invalid credentials",
+            )),
+        }
+    }
+}
+
+/// Middleware for routes which read state. Open when no credentials are configured.
+pub async fn require_read(
+    State(auth): State<Auth>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match auth.authorize(Role::ReadOnly, req).await {
+        Ok(req) => next.run(req).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Middleware for routes which change state. Off when no credentials are configured.
+pub async fn require_admin(
+    State(auth): State<Auth>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match auth.authorize(Role::Admin, req).await {
+        Ok(req) => next.run(req).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// The text a request signature is computed over: the method, the path and query, the
+/// timestamp and the hex encoded SHA-256 digest of the body, separated by newlines
+fn signed_message(method: &Method, uri: &Uri, timestamp: u64, body: &[u8]) -> String {
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let digest = digest::digest(&digest::SHA256, body);
+    format!(
+        "{}\n{}\n{}\n{}",
+        method,
+        path,
+        timestamp,
+        to_hex(digest.as_ref())
+    )
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Compare secrets in time which doesn't depend on where they first differ
+fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> Auth {
+        let options: ApiOptions = serde_json::from_value(serde_json::json!({
+            "tokens": ["s3cret", {"token": "viewer", "role": "read_only"}],
+            "hmac_keys": [{"id": "deploy", "secret": "k3y", "role": "admin"}],
+        }))
+        .unwrap();
+        options.validate().unwrap();
+        Auth::new(&options)
+    }
+
+    fn request(method: Method, authorization: Option<&str>, body: &str) -> Request<Body> {
+        let mut req = Request::builder().method(method).uri("/reload?x=1");
+        if let Some(a) = authorization {
+            req = req.header(header::AUTHORIZATION, a);
+        }
+        req.body(Body::from(body.to_string())).unwrap()
+    }
+
+    fn sign(secret: &str, method: &Method, timestamp: u64, body: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let uri = Uri::from_static("/reload?x=1");
+        let message = signed_message(method, &uri, timestamp, body.as_bytes());
+        let tag = hmac::sign(&key, message.as_bytes());
+        format!("HMAC deploy:{}:{}", timestamp, to_hex(tag.as_ref()))
+    }
+
+    async fn code(auth: &Auth, required: Role, req: Request<Body>) -> Option<ErrorCode> {
+        auth.authorize(required, req).await.err().map(|e| e.code)
+    }
+
+    #[tokio::test]
+    async fn bearer_tokens_have_roles() {
+        let auth = auth();
+        let get = |a| request(Method::GET, a, "");
+        assert_eq!(
+            code(&auth, Role::Admin, get(Some("Bearer s3cret"))).await,
+            None
+        );
+        assert_eq!(
+            code(&auth, Role::ReadOnly, get(Some("Bearer viewer"))).await,
+            None
+        );
+        assert_eq!(
+            code(&auth, Role::Admin, get(Some("Bearer viewer"))).await,
+            Some(ErrorCode::Forbidden)
+        );
+        assert_eq!(
+            code(&auth, Role::ReadOnly, get(Some("Bearer s3cre"))).await,
+            Some(ErrorCode::Unauthorized)
+        );
+        assert_eq!(
+            code(&auth, Role::ReadOnly, get(None)).await,
+            Some(ErrorCode::Unauthorized)
+        );
+
+        // Without credentials reads are open and changes are off
+        let open = Auth::default();
+        assert_eq!(code(&open, Role::ReadOnly, get(None)).await, None);
+        assert_eq!(
+            code(&open, Role::Admin, get(Some("Bearer "))).await,
+            Some(ErrorCode::Forbidden)
+        );
+    }
+
+    #[tokio::test]
+    async fn signed_requests_cover_the_body() {
+        let auth = auth();
+        let now = unix_time().unwrap();
+        let signature = sign("k3y", &Method::POST, now, "{}");
+
+        let req = auth
+            .authorize(Role::Admin, request(Method::POST, Some(&signature), "{}"))
+            .await
+            .unwrap();
+        // The body is still there for the handler
+        let body = Bytes::from_request(req, &()).await.unwrap();
+        assert_eq!(&body[..], b"{}");
+        // A signed request can only be sent once
+        assert_eq!(
+            code(
+                &auth,
+                Role::Admin,
+                request(Method::POST, Some(&signature), "{}")
+            )
+            .await,
+            Some(ErrorCode::Unauthorized)
+        );
+
+        for req in [
+            // Another body, method, key or an old signature
+            request(Method::POST, Some(&signature), "{\"a\":1}"),
+            request(Method::PUT, Some(&signature), "{}"),
+            request(
+                Method::POST,
+                Some(&sign("other", &Method::POST, now, "{}")),
+                "{}",
+            ),
+            request(
+                Method::POST,
+                Some(&sign("k3y", &Method::POST, now - 600, "{}")),
+                "{}",
+            ),
+            request(Method::POST, Some("HMAC deploy:zz"), "{}"),
+        ] {
+            assert_eq!(
+                code(&auth, Role::Admin, req).await,
+                Some(ErrorCode::Unauthorized)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unusable_credentials() {
+        let options = |value| serde_json::from_value::<ApiOptions>(value).unwrap();
+        assert!(options(serde_json::json!({"tokens": [""]}))
+            .validate()
+            .is_err());
+        let key = serde_json::json!({"id": "a", "secret": "b", "role": "admin"});
+        assert!(options(serde_json::json!({"hmac_keys": [key, key]}))
+            .validate()
+            .is_err());
+    }
+}
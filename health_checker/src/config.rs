@@ -0,0 +1,232 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::ApiOptions;
+use crate::dns::DnsOptions;
+use crate::healthcheck::{PollType, Pool};
+use crate::persist::{self, PersistOptions};
+use crate::resolver::ResolverOptions;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Deserialize)]
+///Top level configuration file
+pub struct Config {
+    pub pools: Vec<Pool>,
+    pub dns: Option<DnsOptions>,
+    pub persistence: Option<PersistOptions>,
+    pub resolver: Option<ResolverOptions>,
+    pub api: Option<ApiOptions>,
+}
+
+impl Config {
+    /// Check the parts of the config which parse fine but can't work
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        validate_pools(&self.pools)?;
+        if let Some(api) = &self.api {
+            api.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Check a set of pools which parse fine but can't work together or on their own
+pub fn validate_pools(pools: &[Pool]) -> Result<(), Box<dyn Error>> {
+    let mut names = HashSet::new();
+    for pool in pools {
+        if !names.insert(pool.name.to_ascii_lowercase()) {
+            return Err(format!("Duplicate pool name: {}", pool.name).into());
+        }
+        if pool.interval == 0 {
+            return Err(format!("Pool {}: interval must be at least 1", pool.name).into());
+        }
+        if pool.timeout == 0 {
+            return Err(format!("Pool {}: timeout must be at least 1", pool.name).into());
+        }
+        if pool.rise == 0 || pool.fall == 0 {
+            return Err(format!("Pool {}: rise and fall must be at least 1", pool.name).into());
+        }
+        if let PollType::HTTP = pool.poll_type {
+            if pool.http_options.is_none() {
+                return Err(
+                    format!("Pool {}: poll_type HTTP requires http_options", pool.name).into(),
+                );
+            }
+        }
+        // Members are tracked by host, so each host can only appear once in a pool
+        let mut hosts = HashSet::new();
+        for member in &pool.members {
+            if !hosts.insert(member.host.as_str()) {
+                return Err(format!("Pool {}: duplicate member {}", pool.name, member.host).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read and validate the config file
+pub fn read_config(path: &Path) -> Result<Config, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+
+    let conf: Config = serde_json::from_reader(reader)?;
+    conf.validate()?;
+
+    Ok(conf)
+}
+
+/// Replace the entry for the pool `name` in the config file with `pool`, or remove it when `pool`
+/// is `None`. A new pool is added at the end. Every other part of the file, including the entries
+/// for the other pools, is left as it was. The file is replaced atomically.
+pub fn write_pool(path: &Path, name: &str, pool: Option<&Pool>) -> Result<(), Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut conf: serde_json::Value = serde_json::from_reader(reader)?;
+    let entries = match conf.get_mut("pools").and_then(|p| p.as_array_mut()) {
+        Some(e) => e,
+        None => return Err(format!("{} has no pools list", path.display()).into()),
+    };
+
+    let index = entries.iter().position(|p| p["name"] == name);
+    match (index, pool) {
+        (Some(i), Some(pool)) => entries[i] = pool_entry(pool)?,
+        (None, Some(pool)) => entries.push(pool_entry(pool)?),
+        (Some(i), None) => {
+            entries.remove(i);
+        }
+        (None, None) => (),
+    }
+
+    persist::write_atomic(path, &serde_json::to_vec_pretty(&conf)?)?;
+    Ok(())
+}
+
+/// A pool as written to the config file. Unset options are left out rather than written as nulls.
+fn pool_entry(pool: &Pool) -> Result<serde_json::Value, Box<dyn Error>> {
+    fn strip_nulls(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                fields.retain(|_, v| !v.is_null());
+                fields.values_mut().for_each(strip_nulls);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+            _ => (),
+        }
+    }
+    let mut value = serde_json::to_value(pool)?;
+    strip_nulls(&mut value);
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn parse(json: &str) -> Result<(), Box<dyn Error>> {
+        let conf: Config = serde_json::from_str(json)?;
+        conf.validate()
+    }
+
+    #[test]
+    fn accepts_readme_style_config() {
+        parse(
+            r#"{"pools": [{"name": "app", "port": 8080, "members": ["127.0.0.2"],
+                "interval": 30, "poll_type": "TCP"}]}"#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_http_pool_without_options() {
+        let err = parse(
+            r#"{"pools": [{"name": "app", "port": 8080, "members": ["127.0.0.2"],
+                "interval": 30, "poll_type": "HTTP"}]}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("http_options"));
+    }
+
+    #[test]
+    fn rejects_duplicate_pool_names() {
+        let err = parse(
+            r#"{"pools": [
+                {"name": "app", "port": 8080, "members": [], "interval": 30, "poll_type": "TCP"},
+                {"name": "APP", "port": 8080, "members": [], "interval": 30, "poll_type": "TCP"}
+            ]}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Duplicate"));
+    }
+
+    #[test]
+    fn rejects_duplicate_members() {
+        let err = parse(
+            r#"{"pools": [{"name": "app", "port": 8080, "members": ["a", {"host": "a"}],
+                "interval": 30, "poll_type": "TCP"}]}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("duplicate member"));
+    }
+
+    #[test]
+    fn writes_only_the_changed_pool() {
+        let path =
+            std::env::temp_dir().join(format!("health_checker_write_{}.json", std::process::id()));
+        let untouched = r#"{"name": "web", "port": 443, "members": ["10.0.0.1", "10.0.0.2"],
+                "interval": 30, "poll_type": "HTTP",
+                "http_options": {"https_enabled": true, "send": "/health",
+                    "receive_up": {"status_codes": [200]}}}"#;
+        fs::write(
+            &path,
+            format!(
+                r#"{{"dns": {{"listen": "127.0.0.1:5353"}}, "pools": [
+                {{"name": "b", "port": 80, "members": ["b1"], "interval": 30, "poll_type": "TCP"}},
+                {untouched},
+                {{"name": "a", "port": 80, "members": [], "interval": 30, "poll_type": "TCP"}}
+            ]}}"#
+            ),
+        )
+        .unwrap();
+
+        let conf = read_config(&path).unwrap();
+        let mut b = conf.pools[0].clone();
+        b.port = 8080;
+        write_pool(&path, "b", Some(&b)).unwrap();
+        write_pool(&path, "a", None).unwrap();
+        let mut web = conf.pools[1].clone();
+        web.name = "c".into();
+        write_pool(&path, "c", Some(&web)).unwrap();
+
+        let written = read_config(&path).unwrap();
+        let names: Vec<&str> = written.pools.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["b", "web", "c"]);
+        assert_eq!(written.pools[0].port, 8080);
+        assert_eq!(written.dns.unwrap().listen.port(), 5353);
+
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(untouched).unwrap();
+        assert_eq!(raw["pools"][1], expected);
+        // The pools which were written still leave unset options out, nested ones included
+        assert!(!raw["pools"][2].to_string().contains("null"));
+        assert!(raw["pools"][2]["http_options"]
+            .get("https_require_validity")
+            .is_none());
+    }
+}
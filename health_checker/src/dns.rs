@@ -0,0 +1,479 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::healthcheck::{AddressFamily, HealthTable};
+use crate::metrics::Metrics;
+use crate::selection;
+use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use hickory_proto::rr::{
+    rdata::{A, AAAA},
+    RData, Record, RecordType,
+};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time;
+
+pub const DEFAULT_TTL: u32 = 5;
+
+/// How long a TCP connection may wait on the client before it's closed
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Deserialize)]
+///Configuration for the built-in authoritative DNS server
+pub struct DnsOptions {
+    pub listen: SocketAddr,
+    pub ttl: Option<u32>,
+}
+
+/// Bind the UDP and TCP sockets for the configured listen address. Done before serving so that
+/// a bad address stops the health checker on startup.
+pub async fn bind(options: &DnsOptions) -> Result<(UdpSocket, TcpListener), Box<dyn Error>> {
+    let udp = UdpSocket::bind(options.listen)
+        .await
+        .map_err(|e| format!("Failed to bind DNS UDP socket on {}: {e}", options.listen))?;
+    let tcp = TcpListener::bind(options.listen)
+        .await
+        .map_err(|e| format!("Failed to bind DNS TCP socket on {}: {e}", options.listen))?;
+    Ok((udp, tcp))
+}
+
+/// Long lived DNS server. Answers A and AAAA queries for each pool name over both UDP and TCP on the
+/// sockets from `bind`.
+pub async fn serve(
+    options: DnsOptions,
+    (udp, tcp): (UdpSocket, TcpListener),
+    cache: HealthTable,
+    metrics: Metrics,
+) {
+    let ttl = options.ttl.unwrap_or(DEFAULT_TTL);
+    info!("DNS server listening on {}", options.listen);
+
+    tokio::join!(
+        serve_udp(udp, cache.clone(), metrics.clone(), ttl),
+        serve_tcp(tcp, cache, metrics, ttl)
+    );
+}
+
+/// Answer queries received on a bound UDP socket
+pub async fn serve_udp(socket: UdpSocket, cache: HealthTable, metrics: Metrics, ttl: u32) {
+    let mut buf = [0u8; 4096];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("DNS UDP receive failed: {e}");
+                continue;
+            }
+        };
+        if let Some(response) = handle_query(&buf[..len], &cache, &metrics, ttl) {
+            if let Err(e) = socket.send_to(&response, peer).await {
+                warn!("DNS UDP send to {peer} failed: {e}");
+            }
+        }
+    }
+}
+
+/// Answer queries received on connections to a bound TCP listener
+pub async fn serve_tcp(listener: TcpListener, cache: HealthTable, metrics: Metrics, ttl: u32) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("DNS TCP accept failed: {e}");
+                continue;
+            }
+        };
+        let cache = cache.clone();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_tcp_conn(stream, &cache, &metrics, ttl).await {
+                debug!("DNS TCP connection from {peer} closed: {e}");
+            }
+        });
+    }
+}
+
+/// Handle length-prefixed queries on a single TCP connection until the client hangs up, or
+/// stays idle for longer than `TCP_IDLE_TIMEOUT`
+async fn serve_tcp_conn(
+    mut stream: TcpStream,
+    cache: &HealthTable,
+    metrics: &Metrics,
+    ttl: u32,
+) -> io::Result<()> {
+    loop {
+        let len = idle_timeout(stream.read_u16()).await?;
+        let mut buf = vec![0u8; len.into()];
+        idle_timeout(stream.read_exact(&mut buf)).await?;
+
+        if let Some(response) = handle_query(&buf, cache, metrics, ttl) {
+            idle_timeout(stream.write_u16(response.len() as u16)).await?;
+            idle_timeout(stream.write_all(&response)).await?;
+        }
+    }
+}
+
+/// Fail a read or write on a TCP connection which takes longer than `TCP_IDLE_TIMEOUT`
+async fn idle_timeout<T>(io: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    time::timeout(TCP_IDLE_TIMEOUT, io)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "idle timeout"))?
+}
+
+/// Build the wire-format response for a wire-format query. Returns None if the query can't be
+/// parsed, in which case it is dropped.
+pub fn handle_query(
+    query: &[u8],
+    cache: &HealthTable,
+    metrics: &Metrics,
+    ttl: u32,
+) -> Option<Vec<u8>> {
+    let request = match Message::from_vec(query) {
+        Ok(m) => m,
+        Err(e) => {
+            debug!("Dropping unparseable DNS query: {e}");
+            return None;
+        }
+    };
+
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .set_recursion_desired(request.recursion_desired())
+        .set_authoritative(true);
+
+    let question = match request.queries().first() {
+        Some(q)
+            if request.message_type() == MessageType::Query
+                && request.op_code() == OpCode::Query =>
+        {
+            q.clone()
+        }
+        _ => {
+            response.set_response_code(ResponseCode::NotImp);
+            return response.to_vec().ok();
+        }
+    };
+    response.add_query(question.clone());
+
+    // The pool names in the health table don't carry the trailing "." of a fully qualified name
+    let name = question.name().to_utf8();
+    let name = name.trim_end_matches('.');
+    debug!("DNS question: {} {}", name, question.query_type());
+
+    // Only A and AAAA records are served. ANY is answered with whichever address is selected.
+    let family = match question.query_type() {
+        RecordType::A => Some(AddressFamily::Ipv4),
+        RecordType::AAAA => Some(AddressFamily::Ipv6),
+        RecordType::ANY => Some(AddressFamily::Any),
+        _ => None,
+    };
+
+    let (answer, unknown) = {
+        let mut map = match cache.lock() {
+            Ok(m) => m,
+            Err(_) => {
+                response.set_response_code(ResponseCode::ServFail);
+                return response.to_vec().ok();
+            }
+        };
+        match map
+            .iter_mut()
+            .find(|(pool, _)| pool.eq_ignore_ascii_case(name))
+        {
+            // The pool exists but has no records of this type. Answer with an empty NOERROR.
+            Some(_) if family.is_none() => return response.to_vec().ok(),
+            Some((pool_name, pool)) => {
+                let unknown = pool.health_unknown();
+                let family = family.unwrap_or(AddressFamily::Any);
+                let member = selection::select(pool, family);
+                metrics.lookup(pool_name, "dns", member.is_some_and(|m| m.is_fallback()));
+                (member.map(|m| m.ip), unknown)
+            }
+            None => {
+                response.set_response_code(ResponseCode::NXDomain);
+                return response.to_vec().ok();
+            }
+        }
+    };
+
+    match answer {
+        Some(ip) => {
+            let rdata = match ip {
+                IpAddr::V4(ip) => RData::A(A(ip)),
+                IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+            };
+            response.add_answer(Record::from_rdata(question.name().clone(), ttl, rdata));
+        }
+        // The members haven't been probed yet. SERVFAIL so that resolvers try again soon
+        // rather than caching an empty answer.
+        None if unknown => {
+            response.set_response_code(ResponseCode::ServFail);
+        }
+        // Every member of this family is down and there's no fallback. Answer with an empty
+        // NOERROR.
+        None => {}
+    }
+
+    response.to_vec().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::healthcheck::{Health, Member, PoolHealth};
+    use crate::test_util::member;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::Name;
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    fn table() -> HealthTable {
+        let mut map = HashMap::new();
+        map.insert(
+            String::from("app.example.com"),
+            PoolHealth::new(vec![
+                member("a", [127, 0, 0, 2], Health::Down),
+                member("b", [127, 0, 0, 3], Health::Up),
+            ]),
+        );
+        map.insert(
+            String::from("down.example.com"),
+            PoolHealth::new(vec![member("a", [127, 0, 0, 4], Health::Down)]),
+        );
+        Arc::new(Mutex::new(map))
+    }
+
+    fn query(name: &str, record_type: RecordType) -> Vec<u8> {
+        let mut msg = Message::new();
+        msg.set_id(42)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .add_query(Query::query(Name::from_str(name).unwrap(), record_type));
+        msg.to_vec().unwrap()
+    }
+
+    fn answer_ips(response: &Message) -> Vec<Ipv4Addr> {
+        response
+            .answers()
+            .iter()
+            .filter_map(|r| match r.data() {
+                Some(RData::A(a)) => Some(a.0),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn answers_first_healthy_member() {
+        let response = handle_query(
+            &query("app.example.com.", RecordType::A),
+            &table(),
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+
+        assert_eq!(response.id(), 42);
+        assert!(response.authoritative());
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(answer_ips(&response), vec![Ipv4Addr::new(127, 0, 0, 3)]);
+        assert_eq!(response.answers()[0].ttl(), 5);
+    }
+
+    #[test]
+    fn aaaa_answers_ipv6_members_and_fallback() {
+        let cache = table();
+        {
+            let mut map = cache.lock().unwrap();
+            let pool = map.get_mut("app.example.com").unwrap();
+            let ip: IpAddr = "2001:db8::3".parse().unwrap();
+            pool.members.push(member("c", ip, Health::Up));
+            pool.members
+                .push(Member::fallback("2001:db8::1".parse().unwrap()));
+        }
+
+        let response = handle_query(
+            &query("app.example.com.", RecordType::AAAA),
+            &cache,
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::AAAA(AAAA("2001:db8::3".parse().unwrap())))
+        );
+        // A queries never see the IPv6 member
+        let response = handle_query(
+            &query("app.example.com.", RecordType::A),
+            &cache,
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+        assert_eq!(answer_ips(&response), vec![Ipv4Addr::new(127, 0, 0, 3)]);
+
+        cache
+            .lock()
+            .unwrap()
+            .get_mut("app.example.com")
+            .unwrap()
+            .members[2]
+            .health = Health::Down;
+        let response = handle_query(
+            &query("app.example.com.", RecordType::AAAA),
+            &cache,
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::AAAA(AAAA("2001:db8::1".parse().unwrap())))
+        );
+    }
+
+    #[test]
+    fn unknown_pool_is_nxdomain() {
+        let response = handle_query(
+            &query("nope.example.com.", RecordType::A),
+            &table(),
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+    }
+
+    #[test]
+    fn no_healthy_members_is_empty_noerror() {
+        let response = handle_query(
+            &query("down.example.com.", RecordType::A),
+            &table(),
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+    }
+
+    #[test]
+    fn unknown_health_is_servfail() {
+        let cache = table();
+        cache
+            .lock()
+            .unwrap()
+            .get_mut("down.example.com")
+            .unwrap()
+            .members[0]
+            .health = Health::Unknown;
+        let response = handle_query(
+            &query("down.example.com.", RecordType::A),
+            &cache,
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+    }
+
+    #[test]
+    fn other_record_types_are_empty_noerror() {
+        let response = handle_query(
+            &query("app.example.com.", RecordType::MX),
+            &table(),
+            &Metrics::new(),
+            5,
+        )
+        .unwrap();
+        let response = Message::from_vec(&response).unwrap();
+
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn serves_queries_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(serve_udp(server, table(), Metrics::new(), 5));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(&query("APP.example.com.", RecordType::A), addr)
+            .await
+            .unwrap();
+        let mut buf = [0u8; 512];
+        let len = client.recv(&mut buf).await.unwrap();
+        let response = Message::from_vec(&buf[..len]).unwrap();
+
+        assert_eq!(answer_ips(&response), vec![Ipv4Addr::new(127, 0, 0, 3)]);
+    }
+
+    #[tokio::test]
+    async fn serves_queries_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_tcp(listener, table(), Metrics::new(), 5));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let q = query("app.example.com.", RecordType::A);
+        stream.write_u16(q.len() as u16).await.unwrap();
+        stream.write_all(&q).await.unwrap();
+        let len = stream.read_u16().await.unwrap();
+        let mut buf = vec![0u8; len.into()];
+        stream.read_exact(&mut buf).await.unwrap();
+        let response = Message::from_vec(&buf).unwrap();
+
+        assert_eq!(answer_ips(&response), vec![Ipv4Addr::new(127, 0, 0, 3)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_idle_tcp_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_tcp(listener, table(), Metrics::new(), 5));
+
+        // The clock only moves on while the client sends nothing
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let started = time::Instant::now();
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+        assert!(started.elapsed() >= TCP_IDLE_TIMEOUT);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::healthcheck::{unix_time, AdminState, FailureReason, Health};
+use crate::supervisor::ReloadReport;
+use axum::{
+    extract::State,
+    response::sse::{self, KeepAlive, Sse},
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+/// Messages kept for subscribers which fall behind. Slower subscribers are told how many they
+/// missed.
+const BACKLOG: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// A change pushed to `/events` subscribers
+pub enum Event {
+    /// A member's health changed
+    Health {
+        pool: String,
+        host: String,
+        ip: IpAddr,
+        health: Health,
+        /// Why the probe which brought the member down failed
+        failure: Option<FailureReason>,
+        error: Option<String>,
+    },
+    /// A member's administrative state was set, or ran out
+    Admin {
+        pool: String,
+        host: String,
+        admin: AdminState,
+    },
+    /// The config file was reloaded
+    Reload { report: ReloadReport },
+    /// The config file couldn't be reloaded and the running config was kept
+    ReloadFailed { error: String },
+    /// Pools were changed through the API
+    Change { report: ReloadReport },
+    /// Sent to a subscriber which fell behind, in place of the messages it missed
+    Lagged { missed: u64 },
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// An event and when it happened
+pub struct Message {
+    /// Unix time, in seconds
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Sends events from the pollers, the supervisor and the API handlers to `/events` subscribers
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Message>,
+    /// Ends every stream, so shutdown doesn't wait on them
+    closed: CancellationToken,
+}
+
+impl Default for Events {
+    fn default() -> Events {
+        Events {
+            sender: broadcast::channel(BACKLOG).0,
+            closed: CancellationToken::new(),
+        }
+    }
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events::default()
+    }
+
+    /// Send an event to every subscriber. Events are dropped when there are none.
+    pub fn send(&self, event: Event) {
+        let message = Message {
+            at: unix_time().unwrap_or_default(),
+            event,
+        };
+        let _ = self.sender.send(message);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Message> {
+        self.sender.subscribe()
+    }
+
+    /// End every stream. Events sent afterwards are still delivered to receivers from
+    /// `subscribe`.
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    /// Stream of events from now on, as Server-Sent Events with the JSON encoded message as data
+    fn stream(&self) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+        stream::unfold(self.subscribe(), |mut rx| async move {
+            let message = match rx.recv().await {
+                Ok(m) => m,
+                Err(RecvError::Lagged(missed)) => Message {
+                    at: unix_time().unwrap_or_default(),
+                    event: Event::Lagged { missed },
+                },
+                Err(RecvError::Closed) => return None,
+            };
+            let data = serde_json::to_string(&message).unwrap();
+            Some((Ok(sse::Event::default().data(data)), rx))
+        })
+        .take_until(self.closed.clone().cancelled_owned())
+    }
+}
+
+/// Handler for `/events`. Pushes a message whenever a member's health or administrative state
+/// changes, and whenever the pools are reloaded or changed through the API.
+pub async fn stream(
+    State(events): State<Events>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    Sse::new(events.stream()).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn streams_events_until_closed() {
+        let events = Events::new();
+        let mut stream = Box::pin(events.stream());
+        events.send(Event::ReloadFailed {
+            error: "bad config".into(),
+        });
+
+        let Some(Ok(event)) = stream.next().await else {
+            panic!("no event");
+        };
+        // The data is the JSON message, tagged with its type
+        let text = format!("{event:?}");
+        assert!(text.contains(r#"\"type\":\"reload_failed\""#), "{text}");
+        assert!(text.contains(r#"\"error\":\"bad config\""#), "{text}");
+
+        events.close();
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_are_told_what_they_missed() {
+        let events = Events::new();
+        let mut stream = Box::pin(events.stream());
+        for _ in 0..BACKLOG + 3 {
+            events.send(Event::ReloadFailed {
+                error: String::new(),
+            });
+        }
+        let Some(Ok(event)) = stream.next().await else {
+            panic!("no event");
+        };
+        assert!(format!("{event:?}").contains(r#"\"missed\":3"#));
+    }
+}
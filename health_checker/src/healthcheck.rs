@@ -12,73 +12,535 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use log::{error, info, warn};
+use crate::events::{Event, Events};
+use crate::metrics::Metrics;
+use crate::resolver::Resolver;
+use crate::selection::LbMethod;
+use futures_util::future::join_all;
+use log::{debug, error, info, warn};
 use rand::prelude::*;
 use reqwest;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 // use std::future::Pending;
-use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::{net, time};
+use tokio_util::sync::CancellationToken;
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
 pub enum PollType {
     HTTP,
     TCP,
 }
 
-pub type HealthTable = Arc<Mutex<HashMap<String, Vec<Member>>>>;
+impl PollType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PollType::HTTP => "HTTP",
+            PollType::TCP => "TCP",
+        }
+    }
+}
 
-#[derive(Clone, Deserialize)]
+pub type HealthTable = Arc<Mutex<HashMap<String, PoolHealth>>>;
+
+/// Host name given to the member which holds a pool's fallback IP
+pub const FALLBACK_HOST: &str = "fallback";
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
 enum HTTPReceive {
     StatusCodes(Vec<u16>),
     String(String),
 }
 
-#[derive(Clone, Serialize)]
+pub const DEFAULT_WEIGHT: u32 = 1;
+const DEFAULT_MIN_ACTIVE: usize = 1;
+const DEFAULT_THRESHOLD: u32 = 1;
+const DEFAULT_TIMEOUT: u16 = 5;
+
+fn default_weight() -> u32 {
+    DEFAULT_WEIGHT
+}
+
+fn default_min_active() -> usize {
+    DEFAULT_MIN_ACTIVE
+}
+
+fn default_threshold() -> u32 {
+    DEFAULT_THRESHOLD
+}
+
+fn default_timeout() -> u16 {
+    DEFAULT_TIMEOUT
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum MemberConfigRepr {
+    Host(String),
+    Detailed {
+        host: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+        #[serde(default)]
+        priority: u32,
+    },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(from = "MemberConfigRepr")]
+///Configuration of a single pool member. Accepts either a plain host name or an object with a
+///`host` and an optional `weight` and `priority` group.
+pub struct MemberConfig {
+    pub host: String,
+    pub weight: u32,
+    pub priority: u32,
+}
+impl From<MemberConfigRepr> for MemberConfig {
+    fn from(repr: MemberConfigRepr) -> MemberConfig {
+        match repr {
+            MemberConfigRepr::Host(host) => MemberConfig {
+                host,
+                weight: DEFAULT_WEIGHT,
+                priority: 0,
+            },
+            MemberConfigRepr::Detailed {
+                host,
+                weight,
+                priority,
+            } => MemberConfig {
+                host,
+                weight,
+                priority,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Health of a member. Unknown members haven't been probed yet and are never selected.
+pub enum Health {
+    #[default]
+    Up,
+    Down,
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Administrative state of a member, set through the API independently of its health
+pub enum AdminMode {
+    #[default]
+    Enabled,
+    /// Never selected, but still probed and reported with its real health. Used to drain a
+    /// member.
+    Disabled,
+    /// Never selected, and reported as down whatever its probes say
+    ForcedOffline,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+/// Administrative state of a member, why it was set and when it runs out
+pub struct AdminState {
+    pub state: AdminMode,
+    pub reason: Option<String>,
+    /// Unix time, in seconds, after which the member is enabled again
+    pub until: Option<u64>,
+}
+
+impl AdminState {
+    /// The state in effect now, taking the expiry into account
+    pub fn mode(&self) -> AdminMode {
+        match self.until {
+            Some(until) if unix_time().is_some_and(|now| now >= until) => AdminMode::Enabled,
+            _ => self.state,
+        }
+    }
+
+    /// Go back to enabled once the expiry has passed. Returns true if the state expired.
+    pub fn expire(&mut self) -> bool {
+        if self.state != AdminMode::Enabled && self.mode() == AdminMode::Enabled {
+            *self = AdminState::default();
+            return true;
+        }
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// IP version used to resolve and probe members, and to filter members in lookups
+pub enum AddressFamily {
+    #[default]
+    Ipv4,
+    Ipv6,
+    /// Whichever address the resolver returns first. As a lookup filter, any member matches.
+    Any,
+}
+
+impl AddressFamily {
+    pub fn matches(self, ip: &IpAddr) -> bool {
+        match self {
+            AddressFamily::Ipv4 => ip.is_ipv4(),
+            AddressFamily::Ipv6 => ip.is_ipv6(),
+            AddressFamily::Any => true,
+        }
+    }
+
+    /// Address to store for a member which couldn't be resolved
+    pub fn unspecified(self) -> IpAddr {
+        match self {
+            AddressFamily::Ipv6 => Ipv6Addr::UNSPECIFIED.into(),
+            _ => Ipv4Addr::UNSPECIFIED.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Why a probe failed
+pub enum FailureReason {
+    /// The member host couldn't be resolved and has no last known address
+    DnsError,
+    /// No connection or response within the pool's timeout
+    Timeout,
+    Refused,
+    /// The connection failed for a reason other than being refused
+    ConnectError,
+    TlsError,
+    /// The HTTP response didn't have one of the expected status codes
+    StatusMismatch,
+    /// The HTTP response body didn't contain the expected string
+    BodyMismatch,
+    /// The HTTP request couldn't be built, sent or read
+    RequestError,
+}
+
+impl FailureReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureReason::DnsError => "dns_error",
+            FailureReason::Timeout => "timeout",
+            FailureReason::Refused => "refused",
+            FailureReason::ConnectError => "connect_error",
+            FailureReason::TlsError => "tls_error",
+            FailureReason::StatusMismatch => "status_mismatch",
+            FailureReason::BodyMismatch => "body_mismatch",
+            FailureReason::RequestError => "request_error",
+        }
+    }
+
+    /// Classify a failed connection attempt
+    fn from_io(e: &std::io::Error) -> FailureReason {
+        match e.kind() {
+            std::io::ErrorKind::ConnectionRefused => FailureReason::Refused,
+            std::io::ErrorKind::TimedOut => FailureReason::Timeout,
+            // TLS errors are passed up from the TLS stream as invalid data
+            std::io::ErrorKind::InvalidData => FailureReason::TlsError,
+            _ => FailureReason::ConnectError,
+        }
+    }
+
+    /// Classify a failed HTTP request by the first IO error behind it
+    fn from_reqwest(e: &reqwest::Error) -> FailureReason {
+        if e.is_timeout() {
+            return FailureReason::Timeout;
+        }
+        let mut source = std::error::Error::source(e);
+        while let Some(err) = source {
+            if let Some(io) = err.downcast_ref::<std::io::Error>() {
+                return FailureReason::from_io(io);
+            }
+            source = err.source();
+        }
+        match e.is_connect() {
+            true => FailureReason::ConnectError,
+            false => FailureReason::RequestError,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+/// Outcome of a single probe of a member address
+pub struct ProbeResult {
+    /// Unix time, in seconds, the probe finished
+    pub checked_at: u64,
+    /// Milliseconds the probe took, including reading the response body
+    pub response_ms: u64,
+    /// Status code of the HTTP response, if one was received
+    pub status_code: Option<u16>,
+    /// Why the probe failed. None if it succeeded.
+    pub failure: Option<FailureReason>,
+    /// Details of the failure
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    fn passed(started: Instant) -> ProbeResult {
+        ProbeResult {
+            checked_at: unix_time().unwrap_or_default(),
+            response_ms: started.elapsed().as_millis() as u64,
+            status_code: None,
+            failure: None,
+            error: None,
+        }
+    }
+
+    fn failed(started: Instant, reason: FailureReason, error: String) -> ProbeResult {
+        ProbeResult {
+            failure: Some(reason),
+            error: Some(error),
+            ..ProbeResult::passed(started)
+        }
+    }
+
+    fn with_status(self, status_code: u16) -> ProbeResult {
+        ProbeResult {
+            status_code: Some(status_code),
+            ..self
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_time() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// Addresses of a member host to probe. Every address if the pool tracks them all, otherwise
+/// just the first. If the lookup fails, the member's last known addresses are used instead, and
+/// the lookup error is only returned if there are none.
+async fn resolve_member(
+    host: &str,
+    pool: &Pool,
+    resolver: &Resolver,
+    cache: &HealthTable,
+) -> Result<Vec<SocketAddr>, String> {
+    match resolver.lookup(host, pool.port, pool.address_family).await {
+        Ok(mut addrs) => {
+            if !pool.resolve_all {
+                addrs.truncate(1);
+            }
+            Ok(addrs)
+        }
+        Err(e) => {
+            let known = last_known(host, pool, cache);
+            if known.is_empty() {
+                warn!("DNS lookup failed for {}: {e}", host);
+                return Err(format!("DNS lookup failed: {e}"));
+            }
+            warn!(
+                "DNS lookup failed for {}: {e}. Probing the last known address.",
+                host
+            );
+            Ok(known)
+        }
+    }
+}
+
+/// Addresses the member host resolved to last time
+fn last_known(host: &str, pool: &Pool, cache: &HealthTable) -> Vec<SocketAddr> {
+    let pools = cache.lock().unwrap();
+    let members = match pools.get(&pool.name) {
+        Some(p) => &p.members,
+        None => return Vec::new(),
+    };
+    members
+        .iter()
+        .filter(|m| !m.is_fallback() && m.host == host && !m.ip.is_unspecified())
+        .map(|m| SocketAddr::new(m.ip, pool.port))
+        .collect()
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Member {
     pub host: String,
-    pub ip: Ipv4Addr,
-    pub healthy: bool,
-    pub cancel: bool,
+    pub ip: IpAddr,
+    pub health: Health,
+    pub weight: u32,
+    /// Priority group. Higher groups are preferred.
+    pub priority: u32,
+    pub consecutive_successes: u32,
+    pub consecutive_failures: u32,
+    /// Unix time, in seconds, of the last change in health
+    pub last_transition: Option<u64>,
+    /// Result of the last probe, including why it failed
+    #[serde(default)]
+    pub last_probe: Option<ProbeResult>,
+    /// Administrative state, kept across reloads
+    #[serde(default)]
+    pub admin: AdminState,
 }
 impl PartialEq for Member {
     fn eq(&self, rhs: &Member) -> bool {
-        self.host == rhs.host && self.ip == rhs.ip
+        self.host == rhs.host
+            && self.ip == rhs.ip
+            && self.weight == rhs.weight
+            && self.priority == rhs.priority
     }
 }
 impl Member {
-    pub fn new(host: &String) -> Member {
-        let host_socket_string = format!("{}:{}", host, 443);
-
-        // Get the first ipv4 address and ignore the rest
-        // Explode if after filtering for an ipv4 addr, an ipv6 addr is parsed.
-        // TODO(alb): this could probably be refactored to make it easier to read
-        let resolved_addr: IpAddr = match host_socket_string.to_socket_addrs() {
-            Ok(mut socket) => match socket.find(|ip| ip.is_ipv4()) {
-                Some(socket_addr) => socket_addr.ip(),
-                None => [0, 0, 0, 0].into(),
-            },
-            Err(_) => [0, 0, 0, 0].into(),
-        };
+    /// Build the member which holds a pool's fallback IP. It is always healthy and never polled.
+    pub fn fallback(ip: IpAddr) -> Member {
+        Member {
+            host: FALLBACK_HOST.into(),
+            ip,
+            health: Health::Up,
+            weight: DEFAULT_WEIGHT,
+            priority: 0,
+            consecutive_successes: 0,
+            consecutive_failures: 0,
+            last_transition: None,
+            last_probe: None,
+            admin: AdminState::default(),
+        }
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.host == FALLBACK_HOST
+    }
+
+    /// Whether the member is healthy and hasn't been forced offline
+    pub fn is_up(&self) -> bool {
+        self.health == Health::Up && self.admin.mode() != AdminMode::ForcedOffline
+    }
+
+    /// Whether the member may be selected, health permitting
+    pub fn is_enabled(&self) -> bool {
+        self.admin.mode() == AdminMode::Enabled
+    }
 
-        let resolved_v4 = match resolved_addr{
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(_) => panic!("Found IPv6 after filtering out IPv6 addresses while trying to resolve hostname: {}", &host),
+    /// Build the members for a configured host. The host is resolved to the first address of
+    /// the pool's family, or to every address if the pool tracks them all. A host which can't be
+    /// resolved yet gets a single member with the unspecified address, which is never selected.
+    pub async fn from_config(
+        config: &MemberConfig,
+        pool: &Pool,
+        resolver: &Resolver,
+    ) -> Vec<Member> {
+        let mut addrs = match resolver
+            .lookup(&config.host, 443, pool.address_family)
+            .await
+        {
+            Ok(a) => a,
+            Err(e) => {
+                warn!("DNS lookup failed for {}: {e}", config.host);
+                let ip = pool.address_family.unspecified();
+                return vec![Member::new(config, pool.initial_state, ip)];
+            }
         };
+        if !pool.resolve_all {
+            addrs.truncate(1);
+        }
+        addrs
+            .iter()
+            .map(|a| Member::new(config, pool.initial_state, a.ip()))
+            .collect()
+    }
 
+    pub fn new(config: &MemberConfig, initial_state: Health, ip: IpAddr) -> Member {
         Member {
-            host: host.clone(),
-            ip: resolved_v4,
-            healthy: true,
-            cancel: false,
+            host: config.host.clone(),
+            ip,
+            health: initial_state,
+            weight: config.weight,
+            priority: config.priority,
+            consecutive_successes: 0,
+            consecutive_failures: 0,
+            last_transition: None,
+            last_probe: None,
+            admin: AdminState::default(),
+        }
+    }
+
+    /// Count a probe result against the member. Health only changes once `rise` consecutive
+    /// successes or `fall` consecutive failures have been seen, except for a member in the
+    /// unknown state which takes the result of its first probe. Returns true if the health
+    /// changed.
+    pub fn record_probe(&mut self, up: bool, rise: u32, fall: u32) -> bool {
+        if up {
+            self.consecutive_successes = self.consecutive_successes.saturating_add(1);
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+            self.consecutive_successes = 0;
+        }
+
+        let flip = match (self.health, up) {
+            (Health::Unknown, _) => true,
+            (Health::Down, true) => self.consecutive_successes >= rise,
+            (Health::Up, false) => self.consecutive_failures >= fall,
+            _ => false,
+        };
+        if flip {
+            self.health = if up { Health::Up } else { Health::Down };
+            self.last_transition = unix_time();
         }
+        flip
     }
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+/// Health state of a single pool. Members are kept in priority order with the fallback, if any,
+/// last.
+pub struct PoolHealth {
+    pub members: Vec<Member>,
+    #[serde(skip)]
+    pub lb_method: LbMethod,
+    /// Minimum number of healthy members before the next priority group is also used
+    #[serde(skip)]
+    pub min_active: usize,
+    /// Position of the next round robin selection
+    #[serde(skip)]
+    pub cursor: usize,
+}
+impl PoolHealth {
+    pub fn new(members: Vec<Member>) -> PoolHealth {
+        PoolHealth {
+            members,
+            min_active: DEFAULT_MIN_ACTIVE,
+            ..Default::default()
+        }
+    }
+
+    /// Apply the selection settings of the pool's configuration
+    pub fn configure(&mut self, pool: &Pool) {
+        self.lb_method = pool.lb_method;
+        self.min_active = pool.min_active_members;
+    }
+
+    /// True when no enabled member is up yet and at least one hasn't been probed. Lookups should
+    /// treat this as a temporary condition rather than the pool being down.
+    pub fn health_unknown(&self) -> bool {
+        let members = self
+            .members
+            .iter()
+            .filter(|m| !m.is_fallback() && m.is_enabled());
+        let mut unknown = false;
+        for m in members {
+            match m.health {
+                Health::Up => return false,
+                Health::Unknown => unknown = true,
+                Health::Down => {}
+            }
+        }
+        unknown
+    }
+}
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
 ///Configuration relevant to the HTTP poll type
 pub struct HTTPOptions {
     https_enabled: bool,
@@ -87,20 +549,119 @@ pub struct HTTPOptions {
     receive_up: HTTPReceive,
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 ///Configuration relevant to a pool to be checked.
 pub struct Pool {
     pub name: String, //FQDN label for load balanced app
     pub port: u16,
     pub interval: u16,
-    pub members: Vec<String>, //Pool member FQDNs
+    pub members: Vec<MemberConfig>, //Pool member FQDNs
     pub poll_type: PollType,
     pub http_options: Option<HTTPOptions>,
     pub fallback_ip: Option<Ipv4Addr>,
+    /// Served to AAAA lookups when no IPv6 member is healthy
+    pub fallback_ipv6: Option<Ipv6Addr>,
+    /// IP version used to resolve and probe the members
+    #[serde(default)]
+    pub address_family: AddressFamily,
+    /// Track and probe every address a member host resolves to as its own member, rather than
+    /// only the first
+    #[serde(default)]
+    pub resolve_all: bool,
+    #[serde(default)]
+    pub lb_method: LbMethod,
+    #[serde(default = "default_min_active")]
+    pub min_active_members: usize,
+    /// Consecutive successful probes before an unhealthy member is marked healthy
+    #[serde(default = "default_threshold")]
+    pub rise: u32,
+    /// Consecutive failed probes before a healthy member is marked unhealthy
+    #[serde(default = "default_threshold")]
+    pub fall: u32,
+    /// Seconds to wait for a probe before counting it as failed
+    #[serde(default = "default_timeout")]
+    pub timeout: u16,
+    /// Health of each member before its first probe. Pools which don't start up are probed
+    /// immediately on startup instead of after the random backoff.
+    #[serde(default)]
+    pub initial_state: Health,
 }
 
-/// Long lived poller for TCP health checks.
-pub async fn tcp_poller(pool: Arc<Pool>, host: String, cache: HealthTable) {
+impl Pool {
+    /// Whether both configs probe their members the same way. Pollers only need restarting on
+    /// reload when this is false.
+    pub fn same_probe(&self, other: &Pool) -> bool {
+        self.port == other.port
+            && self.interval == other.interval
+            && self.poll_type == other.poll_type
+            && self.http_options == other.http_options
+            && self.timeout == other.timeout
+            && self.rise == other.rise
+            && self.fall == other.fall
+            && self.initial_state == other.initial_state
+            && self.address_family == other.address_family
+            && self.resolve_all == other.resolve_all
+    }
+
+    /// Whether both configs answer lookups the same way. These settings are applied on reload
+    /// without restarting any pollers.
+    pub fn same_lookup(&self, other: &Pool) -> bool {
+        self.lb_method == other.lb_method
+            && self.min_active_members == other.min_active_members
+            && self.fallback_ip == other.fallback_ip
+            && self.fallback_ipv6 == other.fallback_ipv6
+    }
+
+    /// Members for the fallback IPs of the pool, IPv4 first
+    pub fn fallback_members(&self) -> Vec<Member> {
+        let v4 = self.fallback_ip.map(|ip| Member::fallback(ip.into()));
+        let v6 = self.fallback_ipv6.map(|ip| Member::fallback(ip.into()));
+        v4.into_iter().chain(v6).collect()
+    }
+}
+
+/// Long lived poller for TCP health checks. Runs until `cancel` is cancelled.
+pub async fn tcp_poller(
+    pool: Arc<Pool>,
+    host: String,
+    cache: HealthTable,
+    metrics: Metrics,
+    events: Events,
+    resolver: Resolver,
+    cancel: CancellationToken,
+) {
+    let label = format!("{}: {}", pool.name, host);
+    tokio::select! {
+        _ = cancel.cancelled() => info!("Stopped poller for {label}"),
+        _ = poll_tcp(pool, host, cache, metrics, events, resolver) => {}
+    }
+}
+
+/// Long lived poller for HTTP(s) health checks. Runs until `cancel` is cancelled.
+pub async fn http_poller(
+    pool: Arc<Pool>,
+    host: String,
+    cache: HealthTable,
+    metrics: Metrics,
+    events: Events,
+    resolver: Resolver,
+    cancel: CancellationToken,
+) {
+    let label = format!("{}: {}", pool.name, host);
+    tokio::select! {
+        _ = cancel.cancelled() => info!("Stopped poller for {label}"),
+        _ = poll_http(pool, host, cache, metrics, events, resolver) => {}
+    }
+}
+
+async fn poll_tcp(
+    pool: Arc<Pool>,
+    host: String,
+    cache: HealthTable,
+    metrics: Metrics,
+    events: Events,
+    resolver: Resolver,
+) {
     // Set backoff to a random integer value between 0 and the interval. At the end of the loop,
     // sleep the difference between the backoff and the configured interval. Ater the sleep, set
     // the interval to 0 so that the sleep is now the same as the interval.
@@ -110,43 +671,78 @@ pub async fn tcp_poller(pool: Arc<Pool>, host: String, cache: HealthTable) {
     let pool = &pool;
     info!("Starting poller for {}: {}", pool.name, &host);
 
-    let backoff = rand::thread_rng().gen_range(0..=pool.interval);
-    let host_socket = format!("{}:{}", host, pool.port);
-
-    info!(
-        "Waiting {} seconds before starting poll for {}: {}",
-        backoff, pool.name, &host
-    );
-    time::sleep(time::Duration::from_secs(backoff.into())).await;
+    let mut pause = initial_backoff(pool, &host).await;
 
     loop {
-        // Resolve the hostname once per iteration
-        // This gets the first ipv4 addr and panics if it finds an ipv6
-        let mut socket = match host_socket.to_socket_addrs() {
+        // Resolve the hostname once per iteration, and connect to the addresses which were found
+        // so the probes use the pool's address family
+        let started = Instant::now();
+        let sockets = match resolve_member(&host, pool, &resolver, &cache).await {
             Ok(s) => s,
-            Err(_) => {
-                warn!("DNS lookup failed for {}", &host);
+            Err(e) => {
+                // Count it as a failed probe of the unresolved member
+                let unresolved = pool.address_family.unspecified();
+                let result = ProbeResult::failed(started, FailureReason::DnsError, e);
+                set_health(
+                    &cache,
+                    &metrics,
+                    &events,
+                    pool,
+                    &host,
+                    &[(unresolved, result)],
+                );
                 time::sleep(time::Duration::from_secs(pool.interval.into())).await;
                 continue;
             }
         };
-        let resolved_addr: Ipv4Addr = match socket
-                .find(|ip| ip.is_ipv4()).expect("No IpV4 addresses found")
-                .ip() {
-                    IpAddr::V4(ip) =>  ip,
-                    IpAddr::V6(_) => panic!("Found IPv6 after filtering out IPv6 addresses while trying to resolve hostname: {}", &host) //This should be impossible.
-                };
-        let conn = net::TcpStream::connect(&host_socket).await;
-        match conn {
-            Ok(_) => set_health(&cache, &pool.name, &host, &resolved_addr, true),
-            Err(_) => set_health(&cache, &pool.name, &host, &resolved_addr, false),
-        }
-        time::sleep(time::Duration::from_secs(pool.interval.into())).await;
+        let results = join_all(
+            sockets
+                .iter()
+                .map(|s| async { (s.ip(), probe_tcp(pool, *s).await) }),
+        )
+        .await;
+        set_health(&cache, &metrics, &events, pool, &host, &results);
+
+        time::sleep(pause).await;
+        pause = time::Duration::from_secs(pool.interval.into());
+    }
+}
+
+/// Try a TCP connection to a single address
+async fn probe_tcp(pool: &Pool, socket: SocketAddr) -> ProbeResult {
+    probe_connect(pool, net::TcpStream::connect(socket)).await
+}
+
+/// Wait for a connection attempt, giving up after the pool's timeout
+async fn probe_connect<T>(
+    pool: &Pool,
+    connect: impl std::future::Future<Output = std::io::Result<T>>,
+) -> ProbeResult {
+    let timeout = time::Duration::from_secs(pool.timeout.into());
+    let started = Instant::now();
+    match time::timeout(timeout, connect).await {
+        Ok(Ok(_)) => ProbeResult::passed(started),
+        Ok(Err(e)) => ProbeResult::failed(
+            started,
+            FailureReason::from_io(&e),
+            format!("TCP connect failed: {e}"),
+        ),
+        Err(_) => ProbeResult::failed(
+            started,
+            FailureReason::Timeout,
+            format!("TCP connect timed out after {:?}", timeout),
+        ),
     }
 }
 
-/// Long lived poller for HTTP(s) health checks.
-pub async fn http_poller(pool: Arc<Pool>, host: String, cache: HealthTable) {
+async fn poll_http(
+    pool: Arc<Pool>,
+    host: String,
+    cache: HealthTable,
+    metrics: Metrics,
+    events: Events,
+    resolver: Resolver,
+) {
     // Set backoff to a random integer value between 0 and the interval. At the end of the loop,
     // sleep the difference between the backoff and the configured interval. Ater the sleep, set
     // the interval to 0 so that the sleep is now the same as the interval.
@@ -170,134 +766,553 @@ pub async fn http_poller(pool: Arc<Pool>, host: String, cache: HealthTable) {
         }
     };
 
+    // IPv6 literals need brackets in a URL
+    let url_host = match host.parse::<Ipv6Addr>() {
+        Ok(_) => format!("[{}]", host),
+        Err(_) => host.clone(),
+    };
     let url = match http_options.https_enabled {
-        true => format!("https://{}:{}{}", host, pool.port, http_options.send),
-        false => format!("http://{}:{}{}", host, pool.port, http_options.send),
+        true => format!("https://{}:{}{}", url_host, pool.port, http_options.send),
+        false => format!("http://{}:{}{}", url_host, pool.port, http_options.send),
     };
 
-    let host_socket = format!("{}:{}", host, pool.port);
-
-    let backoff = rand::thread_rng().gen_range(0..=pool.interval);
-
-    info!(
-        "Waiting {} seconds before starting poll for {}: {}",
-        backoff, pool.name, &host
-    );
-
-    time::sleep(time::Duration::from_secs(backoff.into())).await;
+    let mut pause = initial_backoff(pool, &host).await;
 
     loop {
-        let client = match &http_options.https_enabled {
-            true => reqwest::Client::builder()
-                .danger_accept_invalid_certs(!http_options.https_require_validity.unwrap_or(false))
-                .build()
-                .unwrap(),
-            false => reqwest::Client::builder().build().unwrap(),
-        };
-
         // Resolve the hostname once per iteration
-        // This gets the first ipv4 addr and panics if it finds an ipv6
-        let mut socket = match host_socket.to_socket_addrs() {
+        let started = Instant::now();
+        let sockets = match resolve_member(&host, pool, &resolver, &cache).await {
             Ok(s) => s,
-            Err(_) => {
-                warn!("DNS lookup failed for {}", &host);
+            Err(e) => {
+                // Count it as a failed probe of the unresolved member
+                let unresolved = pool.address_family.unspecified();
+                let result = ProbeResult::failed(started, FailureReason::DnsError, e);
+                set_health(
+                    &cache,
+                    &metrics,
+                    &events,
+                    pool,
+                    &host,
+                    &[(unresolved, result)],
+                );
                 time::sleep(time::Duration::from_secs(pool.interval.into())).await;
                 continue;
             }
         };
-        let resolved_addr: Ipv4Addr = match socket
-                .find(|ip| ip.is_ipv4()).expect("No IpV4 addresses found")
-                .ip() {
-                    IpAddr::V4(ip) =>  ip,
-                    IpAddr::V6(_) => panic!("Found IPv6 after filtering out IPv6 addresses while trying to resolve hostname: {}", &host) //This should be impossible.
-                };
-
-        let req = client.get(&url).build().expect("Failed to build request.");
 
         info!("Checking health at {} for {}", &url, pool.name);
+        let results = join_all(sockets.iter().map(|s| async {
+            (
+                s.ip(),
+                probe_http(pool, http_options, &host, &url, *s).await,
+            )
+        }))
+        .await;
+        set_health(&cache, &metrics, &events, pool, &host, &results);
 
-        // Check if the connection is successful
-        // Mark the app healthy based on the kind of successs criteria defined on the pool
-        match client.execute(req).await {
-            Ok(r) => match &http_options.receive_up {
-                // Status code based healthy conditions
-                HTTPReceive::StatusCodes(codes) => {
-                    if codes.contains(&r.status().as_u16()) {
-                        set_health(&cache, &pool.name, &host, &resolved_addr, true);
-                    } else {
-                        set_health(&cache, &pool.name, &host, &resolved_addr, false);
-                    }
-                }
+        time::sleep(pause).await;
+        pause = time::Duration::from_secs(pool.interval.into());
+    }
+}
+
+/// Send the health check request to a single address of the host
+async fn probe_http(
+    pool: &Pool,
+    http_options: &HTTPOptions,
+    host: &str,
+    url: &str,
+    socket: SocketAddr,
+) -> ProbeResult {
+    let timeout = time::Duration::from_secs(pool.timeout.into());
+    let started = Instant::now();
+    let failed = |reason, error| ProbeResult::failed(started, reason, error);
+
+    // The timeout covers connecting, sending the request and reading the whole body. The
+    // client is pinned to the resolved address, which keeps the Host header and SNI intact.
+    let builder = reqwest::Client::builder()
+        .connect_timeout(timeout)
+        .timeout(timeout)
+        .resolve(host, socket);
+    let builder = match &http_options.https_enabled {
+        true => builder
+            .danger_accept_invalid_certs(!http_options.https_require_validity.unwrap_or(false)),
+        false => builder,
+    };
+    let client = match builder.build() {
+        Ok(c) => c,
+        Err(e) => {
+            let error = format!("Unable to build HTTP client: {e}");
+            return failed(FailureReason::RequestError, error);
+        }
+    };
+
+    let req = match client.get(url).build() {
+        Ok(r) => r,
+        Err(e) => {
+            let error = format!("Unable to build request for {url}: {e}");
+            return failed(FailureReason::RequestError, error);
+        }
+    };
+
+    // Check if the connection is successful
+    // Mark the app healthy based on the kind of successs criteria defined on the pool
+    let r = match client.execute(req).await {
+        Ok(r) => r,
+        Err(e) => {
+            let reason = FailureReason::from_reqwest(&e);
+            let error = match reason {
+                FailureReason::Timeout => format!("HTTP request timed out after {:?}", timeout),
+                _ => format!("HTTP request failed: {e}"),
+            };
+            return failed(reason, error);
+        }
+    };
+    let status = r.status().as_u16();
+    let result = match &http_options.receive_up {
+        // Status code based healthy conditions
+        HTTPReceive::StatusCodes(codes) => match codes.contains(&status) {
+            true => ProbeResult::passed(started),
+            false => failed(
+                FailureReason::StatusMismatch,
+                format!("Unexpected status code {status}"),
+            ),
+        },
 
-                // String matching based healthy conditions
-                HTTPReceive::String(match_string) => {
-                    let r_bytes = match r.bytes().await {
-                        Ok(b) => b,
-                        Err(e) => {
-                            set_health(&cache, &pool.name, &host, &resolved_addr, false);
-                            info!("{e}");
-                            continue;
-                        }
-                    };
-
-                    // Check if the received body contains the match string
-                    if r_bytes
-                        .windows(match_string.as_bytes().len())
-                        .any(|window| window == match_string.as_bytes())
-                    {
-                        set_health(&cache, &pool.name, &host, &resolved_addr, true);
-                    } else {
-                        set_health(&cache, &pool.name, &host, &resolved_addr, false);
+        // String matching based healthy conditions
+        HTTPReceive::String(match_string) => {
+            // Check if the received body contains the match string. A body that can't be read
+            // in full before the timeout counts as a failure.
+            match r.bytes().await {
+                Ok(r_bytes) => {
+                    let found = match_string.is_empty()
+                        || r_bytes
+                            .windows(match_string.len())
+                            .any(|window| window == match_string.as_bytes());
+                    match found {
+                        true => ProbeResult::passed(started),
+                        false => failed(
+                            FailureReason::BodyMismatch,
+                            format!("Response body does not contain {:?}", match_string),
+                        ),
                     }
                 }
-            },
-            Err(_) => set_health(&cache, &pool.name, &host, &resolved_addr, false),
-        };
-        if pending_cancel(&cache, &pool.name, &host) {
-            break;
+                Err(e) => match FailureReason::from_reqwest(&e) {
+                    FailureReason::Timeout => failed(
+                        FailureReason::Timeout,
+                        format!("Reading the response body timed out after {:?}", timeout),
+                    ),
+                    _ => failed(
+                        FailureReason::RequestError,
+                        format!("Unable to read response body: {e}"),
+                    ),
+                },
+            }
         }
+    };
+    result.with_status(status)
+}
 
-        time::sleep(time::Duration::from_secs(pool.interval.into())).await;
+/// Wait out the random startup backoff of a poller and return how long to wait after its first
+/// probe. Pools whose members don't start up skip the wait so their real state is known as soon
+/// as possible, and take the backoff after the first probe instead.
+async fn initial_backoff(pool: &Pool, host: &String) -> time::Duration {
+    let backoff = rand::thread_rng().gen_range(0..=pool.interval);
+    let backoff_duration = time::Duration::from_secs(backoff.into());
+    if pool.initial_state != Health::Up {
+        info!(
+            "Probing {}: {} immediately, then waiting {} seconds",
+            pool.name, &host, backoff
+        );
+        return backoff_duration;
     }
+
+    info!(
+        "Waiting {} seconds before starting poll for {}: {}",
+        backoff, pool.name, &host
+    );
+    time::sleep(backoff_duration).await;
+    time::Duration::from_secs(pool.interval.into())
 }
 
-/// Check for poller cancellation
-fn pending_cancel(cache: &HealthTable, pool_name: &String, host: &String) -> bool {
+/// Record the probe results for a host in the shared cache and the metrics, and send an event
+/// for each change in health. Health follows the pool's rise and fall thresholds.
+///
+/// Pools which track every resolved address keep one member per address of the host. Members
+/// are added for new addresses, in the pool's initial state, and dropped for addresses which no
+/// longer resolve. Other pools keep a single member per host, which moves to the new address if
+/// the host's address changes.
+fn set_health(
+    cache: &HealthTable,
+    metrics: &Metrics,
+    events: &Events,
+    pool: &Pool,
+    host: &String,
+    results: &[(IpAddr, ProbeResult)],
+) {
     let mut pools = cache.lock().unwrap();
-    if let Some(items) = pools.get_mut(pool_name) {
-        for member in items.iter_mut() {
-            if &member.host == host {
-                if member.cancel {
-                    member.cancel = false;
-                    return true;
-                } else {
-                    return false;
+    let items = match pools.get_mut(&pool.name) {
+        Some(i) => i,
+        None => return,
+    };
+
+    // Take the host's current members out of the pool and put the updated ones back in the
+    // same place
+    let position = match items
+        .members
+        .iter()
+        .position(|m| !m.is_fallback() && &m.host == host)
+    {
+        Some(p) => p,
+        // The member was removed from the config and this poller is about to be stopped. Its
+        // metrics have already been dropped.
+        None => return,
+    };
+    for (_, result) in results {
+        metrics.probe(pool, host, result);
+    }
+    let (mut old, rest): (Vec<Member>, Vec<Member>) = std::mem::take(&mut items.members)
+        .into_iter()
+        .partition(|m| !m.is_fallback() && &m.host == host);
+    items.members = rest;
+    let template = old[0].clone();
+
+    let mut updated = Vec::new();
+    for (ip, result) in results
+        .iter()
+        .take(if pool.resolve_all { usize::MAX } else { 1 })
+    {
+        match &result.error {
+            None => debug!(
+                "Host: {} ({}) probe succeeded in {}ms for {}",
+                &host, ip, result.response_ms, pool.name
+            ),
+            Some(e) => debug!(
+                "Host: {} ({}) probe failed for {}: {}",
+                &host, ip, pool.name, e
+            ),
+        }
+        let mut member = match old.iter().position(|m| m.ip == *ip) {
+            Some(i) => old.remove(i),
+            None if !pool.resolve_all && !old.is_empty() => old.remove(0),
+            None => {
+                if pool.resolve_all {
+                    info!(
+                        "Host: {} resolved to new address {} for {}",
+                        &host, ip, pool.name
+                    );
+                }
+                Member {
+                    health: pool.initial_state,
+                    consecutive_successes: 0,
+                    consecutive_failures: 0,
+                    last_transition: None,
+                    last_probe: None,
+                    ..template.clone()
                 }
             }
+        };
+        member.ip = *ip;
+        member.last_probe = Some(result.clone());
+        if member.admin.expire() {
+            info!(
+                "Host: {} ({}) admin state expired for {}, enabling it",
+                &host, ip, pool.name
+            );
+            events.send(Event::Admin {
+                pool: pool.name.clone(),
+                host: host.clone(),
+                admin: member.admin.clone(),
+            });
+        }
+        if member.record_probe(result.is_ok(), pool.rise, pool.fall) {
+            metrics.transition(&pool.name, host, member.health);
+            events.send(Event::Health {
+                pool: pool.name.clone(),
+                host: host.clone(),
+                ip: *ip,
+                health: member.health,
+                failure: result.failure,
+                error: result.error.clone(),
+            });
+            match &result.error {
+                None => info!("Host: {} ({}) marked healthy for {}", &host, ip, pool.name),
+                Some(e) => info!(
+                    "Host: {} ({}) marked unhealthy for {}: {}",
+                    &host, ip, pool.name, e
+                ),
+            }
+        }
+        updated.push(member);
+    }
+    if pool.resolve_all {
+        for m in old {
+            info!(
+                "Host: {} no longer resolves to {} for {}",
+                &host, m.ip, pool.name
+            );
         }
     }
-    false
+
+    let position = position.min(items.members.len());
+    items.members.splice(position..position, updated);
 }
 
-/// Set the health of the node in the sharead cache
-fn set_health(
-    cache: &HealthTable,
-    pool_name: &String,
-    host: &String,
-    resolved_addr: &Ipv4Addr,
-    health: bool,
-) {
-    match health {
-        true => info!("Host: {} marked healthy for {}", &host, pool_name),
-        false => info!("Host: {} marked unhealthy for {}", &host, pool_name),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{member, pool, table};
+
+    #[test]
+    fn members_accept_plain_and_weighted_forms() {
+        let members: Vec<MemberConfig> =
+            serde_json::from_str(r#"["a.example.com", {"host": "b.example.com", "weight": 70}, {"host": "c.example.com", "priority": 10}]"#)
+                .unwrap();
+
+        assert_eq!(members[0].host, "a.example.com");
+        assert_eq!(members[0].weight, 1);
+        assert_eq!(members[1].host, "b.example.com");
+        assert_eq!(members[1].weight, 70);
+        assert_eq!(members[2].weight, 1);
+        assert_eq!(members[0].priority, 0);
+        assert_eq!(members[2].priority, 10);
     }
-    let mut pools = cache.lock().unwrap();
-    if let Some(items) = pools.get_mut(pool_name) {
-        for member in items.iter_mut() {
-            if &member.host == host {
-                member.healthy = health;
-                member.ip = *resolved_addr;
+
+    #[test]
+    fn health_follows_rise_and_fall() {
+        let mut member = member("127.0.0.1", Ipv4Addr::LOCALHOST, Health::Up);
+        assert!(member.is_up());
+
+        assert!(!member.record_probe(false, 2, 3));
+        assert!(!member.record_probe(false, 2, 3));
+        // A success in between resets the failure count
+        assert!(!member.record_probe(true, 2, 3));
+        assert!(!member.record_probe(false, 2, 3));
+        assert!(!member.record_probe(false, 2, 3));
+        assert!(member.is_up());
+        assert!(member.record_probe(false, 2, 3));
+        assert_eq!(member.health, Health::Down);
+        assert_eq!(member.consecutive_failures, 3);
+        assert!(member.last_transition.is_some());
+
+        assert!(!member.record_probe(true, 2, 3));
+        assert!(member.record_probe(true, 2, 3));
+        assert!(member.is_up());
+        assert_eq!(member.consecutive_successes, 2);
+    }
+
+    #[test]
+    fn unknown_takes_first_probe_result() {
+        let mut down = member("127.0.0.1", Ipv4Addr::LOCALHOST, Health::Unknown);
+        assert!(down.record_probe(false, 2, 3));
+        assert_eq!(down.health, Health::Down);
+
+        let mut up = member("127.0.0.1", Ipv4Addr::LOCALHOST, Health::Unknown);
+        assert!(up.record_probe(true, 2, 3));
+        assert!(up.is_up());
+    }
+
+    fn resolve_all_pool() -> Pool {
+        Pool {
+            resolve_all: true,
+            ..pool("app", 80, &["a", "b"])
+        }
+    }
+
+    fn ips(cache: &HealthTable) -> Vec<(String, String)> {
+        cache.lock().unwrap()["app"]
+            .members
+            .iter()
+            .map(|m| (m.host.clone(), m.ip.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn resolved_addresses_become_members() {
+        let cache = table(
+            "app",
+            vec![
+                member("a", Ipv4Addr::UNSPECIFIED, Health::Up),
+                member("b", Ipv4Addr::UNSPECIFIED, Health::Up),
+            ],
+        );
+        let pool = resolve_all_pool();
+        let (x, y, z): (IpAddr, IpAddr, IpAddr) = (
+            [10, 0, 0, 1].into(),
+            [10, 0, 0, 2].into(),
+            [10, 0, 0, 3].into(),
+        );
+
+        let metrics = Metrics::new();
+        let events = Events::new();
+        let mut received = events.subscribe();
+        let started = Instant::now();
+        let passed = ProbeResult::passed(started);
+        let refused = ProbeResult::failed(started, FailureReason::Refused, "refused".into());
+        set_health(
+            &cache,
+            &metrics,
+            &events,
+            &pool,
+            &"a".into(),
+            &[(x, passed.clone()), (y, refused.clone())],
+        );
+        assert_eq!(
+            ips(&cache),
+            vec![
+                ("a".into(), "10.0.0.1".into()),
+                ("a".into(), "10.0.0.2".into()),
+                ("b".into(), "0.0.0.0".into()),
+            ]
+        );
+        assert!(!cache.lock().unwrap()["app"].members[1].is_up());
+        assert_eq!(
+            cache.lock().unwrap()["app"].members[1].last_probe,
+            Some(refused)
+        );
+        // Only the member which went down is an event
+        assert_eq!(
+            received.try_recv().unwrap().event,
+            Event::Health {
+                pool: "app".into(),
+                host: "a".into(),
+                ip: y,
+                health: Health::Down,
+                failure: Some(FailureReason::Refused),
+                error: Some("refused".into()),
             }
+        );
+        assert!(received.try_recv().is_err());
+
+        // 10.0.0.1 no longer resolves and 10.0.0.3 is new. 10.0.0.2 keeps its state.
+        set_health(
+            &cache,
+            &metrics,
+            &events,
+            &pool,
+            &"a".into(),
+            &[(y, passed.clone()), (z, passed)],
+        );
+        let table = cache.lock().unwrap();
+        let members = &table["app"].members;
+        assert_eq!(members.len(), 3);
+        assert_eq!(members[0].ip, y);
+        assert_eq!(members[0].consecutive_successes, 1);
+        assert_eq!(members[1].ip, z);
+        assert_eq!(members[2].host, "b");
+    }
+
+    #[tokio::test]
+    async fn probe_failures_record_a_reason() {
+        let pool = resolve_all_pool();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = listener.local_addr().unwrap();
+        assert!(probe_tcp(&pool, socket).await.is_ok());
+        drop(listener);
+        let result = probe_tcp(&pool, socket).await;
+        assert_eq!(result.failure, Some(FailureReason::Refused));
+        assert!(result.error.is_some());
+
+        // A send path which doesn't make a valid URL fails the probe instead of the poller
+        let options: HTTPOptions = serde_json::from_value(serde_json::json!({
+            "https_enabled": false,
+            "https_require_validity": null,
+            "send": "/health",
+            "receive_up": {"string": ""},
+        }))
+        .unwrap();
+        let result = probe_http(&pool, &options, "a", "http://a:80 /health", socket).await;
+        assert_eq!(result.failure, Some(FailureReason::RequestError));
+        assert_eq!(result.status_code, None);
+    }
+
+    fn timeout_pool() -> Pool {
+        Pool {
+            poll_type: PollType::HTTP,
+            timeout: 1,
+            ..pool("app", 80, &["a"])
         }
     }
+
+    #[tokio::test]
+    async fn hanging_http_probes_time_out() {
+        // Accepts connections but never answers
+        let listener = net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let pool = timeout_pool();
+        let options: HTTPOptions = serde_json::from_value(serde_json::json!({
+            "https_enabled": false,
+            "https_require_validity": null,
+            "send": "/health",
+            "receive_up": {"status_codes": [200]},
+        }))
+        .unwrap();
+        let url = format!("http://a:{}/health", socket.port());
+        let started = Instant::now();
+        let result = probe_http(&pool, &options, "a", &url, socket).await;
+        assert_eq!(result.failure, Some(FailureReason::Timeout));
+        assert!(started.elapsed() < time::Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hanging_tcp_connects_time_out() {
+        let pool = timeout_pool();
+        let connect = std::future::pending::<std::io::Result<net::TcpStream>>();
+        let result = probe_connect(&pool, connect).await;
+        assert_eq!(result.failure, Some(FailureReason::Timeout));
+        assert!(result.error.unwrap().contains("timed out after 1s"));
+    }
+
+    #[tokio::test]
+    async fn http_probes_record_status_and_mismatch() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 7\r\nconnection: close\r\n\r\nStarted",
+                    )
+                    .await;
+            }
+        });
+
+        let pool = resolve_all_pool();
+        let options = |receive_up: serde_json::Value| -> HTTPOptions {
+            serde_json::from_value(serde_json::json!({
+                "https_enabled": false,
+                "https_require_validity": null,
+                "send": "/health",
+                "receive_up": receive_up,
+            }))
+            .unwrap()
+        };
+        let url = format!("http://a:{}/health", socket.port());
+
+        let opts = options(serde_json::json!({"string": "Start"}));
+        let result = probe_http(&pool, &opts, "a", &url, socket).await;
+        assert!(result.is_ok());
+        assert_eq!(result.status_code, Some(200));
+
+        let opts = options(serde_json::json!({"string": "Healthy"}));
+        let result = probe_http(&pool, &opts, "a", &url, socket).await;
+        assert_eq!(result.failure, Some(FailureReason::BodyMismatch));
+        assert_eq!(result.status_code, Some(200));
+
+        let opts = options(serde_json::json!({"status_codes": [204]}));
+        let result = probe_http(&pool, &opts, "a", &url, socket).await;
+        assert_eq!(result.failure, Some(FailureReason::StatusMismatch));
+        assert_eq!(result.status_code, Some(200));
+    }
 }
@@ -12,36 +12,131 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod api;
+pub mod auth;
+pub mod config;
+pub mod dns;
+pub mod events;
 pub mod healthcheck;
+pub mod metrics;
+pub mod persist;
+pub mod registry;
+pub mod resolver;
+pub mod selection;
+pub mod supervisor;
+#[cfg(test)]
+mod test_util;
 
 use axum::{
-    extract::{Query, State},
+    extract::{FromRef, Query, State},
     http::StatusCode,
-    routing::get,
+    middleware,
+    routing::{get, post},
     Router,
 };
 
 // use reqwest;
-// use env_logger;
+use clap::Parser;
+use config::read_config;
+use events::Events;
+use healthcheck::AddressFamily;
 use log::info;
+use metrics::Metrics;
 use serde::Deserialize;
 // use serde_json;
-use std::fs::File;
-use std::io::BufReader;
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::process;
 use std::sync::{Arc, Mutex};
-use std::{collections::HashMap, error::Error};
-use tokio::task::JoinSet;
+use supervisor::{Supervisor, SupervisorHandle};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Parser)]
+#[command(version, about = "Health checking service for GTM")]
+struct Args {
+    /// Path to the config file
+    #[arg(short, long, default_value = "./conf.json")]
+    config: PathBuf,
+
+    /// Address for the HTTP API to listen on
+    #[arg(short, long, default_value = "0.0.0.0")]
+    listen: IpAddr,
+
+    /// Port for the HTTP API to listen on
+    #[arg(short, long, default_value_t = 8080)]
+    port: u16,
+
+    /// Log level (error, warn, info, debug, trace). Overrides RUST_LOG.
+    #[arg(long)]
+    log_level: Option<log::LevelFilter>,
+
+    /// Reload the config automatically whenever the config file changes
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Seconds to wait for in-flight API requests to finish on shutdown
+    #[arg(long, default_value_t = 10)]
+    shutdown_timeout: u64,
+
+    /// Validate the config file and exit
+    #[arg(long)]
+    check_config: bool,
+}
 
 #[derive(Deserialize)]
 struct QueryParams {
     name: String,
+    /// Only consider members with an address of this family. Any address by default.
+    family: Option<AddressFamily>,
 }
 
-#[derive(Deserialize)]
-struct Config {
-    pools: Vec<healthcheck::Pool>,
+impl QueryParams {
+    fn family(&self) -> AddressFamily {
+        self.family.unwrap_or(AddressFamily::Any)
+    }
+}
+
+/// Shared state for the API handlers
+#[derive(Clone)]
+struct AppState {
+    cache: healthcheck::HealthTable,
+    metrics: Metrics,
+    events: Events,
+    ttl: api::Ttl,
+    supervisor: SupervisorHandle,
+}
+
+impl FromRef<AppState> for healthcheck::HealthTable {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.cache)
+    }
+}
+
+impl FromRef<AppState> for Metrics {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for Events {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for api::Ttl {
+    fn from_ref(state: &AppState) -> Self {
+        state.ttl
+    }
+}
+
+impl FromRef<AppState> for SupervisorHandle {
+    fn from_ref(state: &AppState) -> Self {
+        state.supervisor.clone()
+    }
 }
 
 #[tokio::main]
@@ -50,141 +145,206 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // API SECTION
     // -----------------------------------------------------------------------
 
-    env_logger::init();
+    let args = Args::parse();
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(level) = args.log_level {
+        logger.filter_level(level);
+    }
+    logger.init();
 
-    let cache: healthcheck::HealthTable = Arc::new(Mutex::new(HashMap::new()));
+    if args.check_config {
+        match read_config(&args.config) {
+            Ok(_) => {
+                println!("{}: OK", args.config.display());
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", args.config.display());
+                process::exit(1);
+            }
+        }
+    }
+
+    let addr = SocketAddr::new(args.listen, args.port);
 
-    // TODO(alb): Separate into multiple IP info routes by type
-    // e.g. "global availability", "round robin", "random"
+    let conf = match read_config(&args.config) {
+        Ok(c) => c,
+        Err(e) => {
+            log::error!("Failed to load config file {}: {e}", args.config.display());
+            process::exit(1);
+        }
+    };
+    let api_options = conf.api.clone().unwrap_or_default();
 
-    let t = Arc::clone(&cache);
+    let cache: healthcheck::HealthTable = Arc::new(Mutex::new(HashMap::new()));
+    let metrics = Metrics::new();
+    let events = Events::new();
+
+    let (handle, requests) = Supervisor::channel();
+    tokio::spawn(supervisor::reload_on_sighup(handle.clone()));
+    if args.watch {
+        tokio::spawn(supervisor::reload_on_change(
+            args.config.clone(),
+            handle.clone(),
+        ));
+    }
+    let app_state = AppState {
+        cache: Arc::clone(&cache),
+        metrics: metrics.clone(),
+        events: events.clone(),
+        // Lookups report the TTL the DNS server would answer with
+        ttl: api::Ttl(
+            conf.dns
+                .as_ref()
+                .and_then(|d| d.ttl)
+                .unwrap_or(dns::DEFAULT_TTL),
+        ),
+        supervisor: handle,
+    };
+    let auth = auth::Auth::new(&api_options);
+    let read = || middleware::from_fn_with_state(auth.clone(), auth::require_read);
+    let admin = || middleware::from_fn_with_state(auth.clone(), auth::require_admin);
     let app = Router::new()
         .route("/healthz", get(healthz))
         .route("/livez", get(livez))
         .route("/info", get(info))
         .route("/priority-order", get(handle_priority_order))
-        .route("/randommember", get(info))
-        .route("/reset", get(reset))
-        .route("/dump", get(dump_table))
-        .route("/reload", get(reload))
-        .with_state(t);
-
-    info!("Starting API");
+        .route("/random", get(handle_random_order))
+        .route("/randommember", get(handle_random_order))
+        .route("/round-robin", get(handle_round_robin))
+        .route("/addresses", get(handle_addresses))
+        .route("/dump", get(dump_table).route_layer(read()))
+        .route("/metrics", get(handle_metrics).route_layer(read()))
+        .route("/reload", post(reload).route_layer(admin()))
+        .route("/events", get(events::stream).route_layer(read()))
+        .nest("/api/v1", api::routes(auth.clone()))
+        .with_state(app_state);
+
+    info!("Starting API on {addr}");
+
+    let server = match axum::Server::try_bind(&addr) {
+        Ok(s) => s,
+        Err(e) => {
+            log::error!("Failed to bind API to {addr}: {e}");
+            process::exit(1);
+        }
+    };
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_on_signal(shutdown.clone()));
 
-    tokio::spawn(
-        axum::Server::bind(&addr)
+    // Stops accepting connections as soon as shutdown starts, then waits for in-flight requests
+    let api = tokio::spawn(
+        server
             .tcp_nodelay(true)
-            .serve(app.into_make_service()),
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned()),
     );
     info!("API started");
 
     // -----------------------------------------------------------------------
-    // HEALTH CHECKER SECTION
+    // DNS SECTION
     // -----------------------------------------------------------------------
-    info!("Starting health checkers");
-    loop {
-        let conf = match read_config(String::from("./conf.json")) {
-            Ok(c) => c,
+    if let Some(dns_options) = conf.dns.clone() {
+        info!("Starting DNS server");
+        let sockets = dns::bind(&dns_options).await?;
+        tokio::spawn(dns::serve(
+            dns_options,
+            sockets,
+            Arc::clone(&cache),
+            metrics.clone(),
+        ));
+    }
+
+    // -----------------------------------------------------------------------
+    // PERSISTENCE SECTION
+    // -----------------------------------------------------------------------
+    // Load the last saved health state now, but only apply it once the table has been built from
+    // the config below.
+    let mut snapshot = None;
+    if let Some(persist_options) = conf.persistence.clone() {
+        snapshot = match persist::load(&persist_options.path) {
+            Ok(s) => Some(s),
             Err(e) => {
-                log::error!("Failed to parse config file: {e}");
-                process::exit(1);
+                log::warn!(
+                    "Unable to load saved health state from {}: {e}",
+                    persist_options.path.display()
+                );
+                None
             }
         };
+        tokio::spawn(persist::snapshot_loop(persist_options, Arc::clone(&cache)));
+    }
 
-        for p in &conf.pools {
-            let mut members: Vec<healthcheck::Member> =
-                p.members.iter().map(healthcheck::Member::new).collect();
-            if let Some(fallback_ip) = p.fallback_ip {
-                members.push(healthcheck::Member {
-                    host: "fallback".into(),
-                    ip: fallback_ip,
-                    healthy: true,
-                    cancel: false,
-                });
-            }
-            let t = Arc::clone(&cache);
-            let mut items = t.lock().unwrap();
-            if !items.contains_key(&p.name) {
-                items.insert(p.name.clone(), members);
-            } else if let Some(pool) = items.get_mut(&p.name) {
-                if *pool != members {
-                    *pool = members;
-                }
-            }
-        }
+    // -----------------------------------------------------------------------
+    // HEALTH CHECKER SECTION
+    // -----------------------------------------------------------------------
+    info!("Starting health checkers");
+    let resolver = resolver::Resolver::new(&conf.resolver.clone().unwrap_or_default());
+    let mut supervisor = Supervisor::new(
+        Arc::clone(&cache),
+        metrics,
+        events.clone(),
+        snapshot,
+        resolver,
+    );
+    if api_options.write_config {
+        supervisor.write_back_to(args.config.clone());
+    }
+    supervisor.apply(conf.pools.clone()).await;
 
-        // Run the "main" loop which calls other apis and updates the cache
-        //
-        // TODO: Reload config on some interrupt (like SIGHUP)
-        //
-        // Order of operations:
-        // 1. Check list of mananged servers.
-        // 2. Spawn a long-lived task for each checked server.
-        // 3. Let each task loop over check interval.
-
-        // Make an HTTP call to the local pingpong server. Return an error up to the tokio runtime
-        // if something goes wrong.
-
-        let mut join_set = JoinSet::new();
-
-        // Build out the table of health checks based on the loaded configuration.
-        // Starts a poller referencing the same shared cache for each member
-
-        // Wrap the pools in Arc so that the compiler can know that they need to be kept after
-        // references to them are passed to the long-lived pollers. The poller potentially never
-        // exit, and we'd prefer to not clone every pool since they're not mutable data.
-        let arc_pools: Vec<Arc<healthcheck::Pool>> =
-            conf.pools.into_iter().map(|pool| Arc::new(pool)).collect();
-        for pool_arc in arc_pools {
-            for member in &pool_arc.members {
-                let t = Arc::clone(&cache);
-                let name = member.clone();
-
-                let pool_ref = Arc::clone(&pool_arc);
-
-                match pool_ref.poll_type {
-                    healthcheck::PollType::HTTP => {
-                        join_set.spawn(healthcheck::http_poller(pool_ref, name, t))
-                    }
-                    healthcheck::PollType::TCP => {
-                        join_set.spawn(healthcheck::tcp_poller(pool_ref, name, t))
-                    }
-                };
-            }
-        }
+    // The DNS server keeps the settings it was started with. Only the pools are reloaded.
+    supervisor
+        .run(args.config, requests, shutdown.clone())
+        .await;
 
-        // while let Some(_res) = join_set.join_next().await {}
-        if let Some(res) = join_set.join_next().await {
-            match res {
-                Ok(_) => {}
-                Err(_) => {
-                    break;
-                }
-            }
+    // -----------------------------------------------------------------------
+    // SHUTDOWN SECTION
+    // -----------------------------------------------------------------------
+    let deadline = time::Instant::now() + time::Duration::from_secs(args.shutdown_timeout);
+    // Event streams never end on their own
+    events.close();
+
+    if let Some(persist_options) = &conf.persistence {
+        match persist::save(&persist_options.path, &cache) {
+            Ok(_) => info!("Saved health state to {}", persist_options.path.display()),
+            Err(e) => log::warn!(
+                "Failed to save health state to {}: {e}",
+                persist_options.path.display()
+            ),
         }
+    }
 
-        info!("Restarting health checkers");
+    match time::timeout_at(deadline, api).await {
+        Ok(_) => info!("Shutdown complete"),
+        Err(_) => log::warn!(
+            "API requests still running after {} seconds, exiting anyway",
+            args.shutdown_timeout
+        ),
     }
     Ok(())
+}
 
-    //     let resp = reqwest::get("http://127.0.0.1:9090/ping")
-    //         .await?
-    //         .text()
-    //         .await?;
-    //     println!("{}", resp);
-    //
-    //     // Grab a lock on the shared data and update the key with the address.
-    //     let t = Arc::clone(&cache);
-    //     {
-    //         let mut ips = t.lock().unwrap();
-    //         ips.insert(String::from("localhost"), Into::into([127, 0, 0, 1]));
-    //     }
-    //
-    //     // Sleep to simulate long-ish running operations.
-    //     tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-    // }
+/// Cancel `shutdown` on SIGTERM or ctrl-c
+async fn shutdown_on_signal(shutdown: CancellationToken) {
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => info!("Received ctrl-c, shutting down"),
+        },
+        // Still shut down cleanly on ctrl-c
+        Err(e) => {
+            log::error!("Unable to listen for SIGTERM, only ctrl-c will shut down cleanly: {e}");
+            match tokio::signal::ctrl_c().await {
+                Ok(_) => info!("Received ctrl-c, shutting down"),
+                Err(e) => {
+                    log::error!("Unable to listen for ctrl-c: {e}");
+                    return;
+                }
+            }
+        }
+    }
+    shutdown.cancel();
 }
 
 /// Service health probe
@@ -197,24 +357,18 @@ async fn livez() -> (StatusCode, &'static str) {
     (StatusCode::OK, "OK")
 }
 
-/// Get the first healthy member of the requested pool
+/// Get a healthy member of the requested pool using the pool's configured load balancing method
 async fn info(
     q: Query<QueryParams>,
     State(state): State<healthcheck::HealthTable>,
+    State(metrics): State<Metrics>,
 ) -> (StatusCode, String) {
-    let map = &state.lock().unwrap();
-    if let Some(item) = map.get(&q.name) {
-        let healthy_members: Vec<&healthcheck::Member> =
-            item.iter().filter(|m| m.healthy).collect();
-
-        if let Some(member) = healthy_members.first() {
-            (StatusCode::OK, member.ip.to_string())
-        } else {
-            (
-                StatusCode::NOT_FOUND,
-                "No healthy members and no fallback".into(),
-            )
-        }
+    let map = &mut state.lock().unwrap();
+    if let Some(item) = map.get_mut(&q.name) {
+        let unknown = item.health_unknown();
+        let member = selection::select(item, q.family());
+        metrics.lookup(&q.name, "info", served_fallback(member));
+        member_response(member, unknown)
     } else {
         (StatusCode::NOT_FOUND, "Not Found".into())
     }
@@ -225,6 +379,7 @@ async fn info(
 async fn handle_priority_order(
     q: Query<QueryParams>,
     State(state): State<healthcheck::HealthTable>,
+    State(metrics): State<Metrics>,
 ) -> (StatusCode, String) {
     let state = &state.lock();
     let map = match state {
@@ -237,62 +392,129 @@ async fn handle_priority_order(
         }
     };
 
-    let members = match map.get(&q.name) {
+    match map.get(&q.name) {
         Some(p) => {
-            let healthy_members: Vec<&healthcheck::Member> =
-                p.iter().filter(|m| m.healthy).collect();
-            healthy_members
+            let unknown = p.health_unknown();
+            let member = selection::priority(p, q.family());
+            metrics.lookup(&q.name, "priority-order", served_fallback(member));
+            member_response(member, unknown)
+        }
+        None => (StatusCode::NOT_FOUND, "Pool not found".into()),
+    }
+}
+
+/// Handler for the random route. Returns a random selection from the healthy members or the
+/// fallback if necessary
+async fn handle_random_order(
+    q: Query<QueryParams>,
+    State(state): State<healthcheck::HealthTable>,
+    State(metrics): State<Metrics>,
+) -> (StatusCode, String) {
+    let state = &state.lock();
+    let map = match state {
+        Ok(m) => m,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".into(),
+            )
         }
-        None => return (StatusCode::NOT_FOUND, "Pool not found".into()),
     };
 
-    if let Some(member) = members.first() {
-        (StatusCode::OK, member.ip.to_string())
-    } else {
-        (
-            StatusCode::NOT_FOUND,
-            "No healthy members and no fallback IP".into(),
-        )
+    match map.get(&q.name) {
+        Some(p) => {
+            let unknown = p.health_unknown();
+            let member = selection::random(p, q.family());
+            metrics.lookup(&q.name, "random", served_fallback(member));
+            member_response(member, unknown)
+        }
+        None => (StatusCode::NOT_FOUND, "Pool not found".into()),
     }
 }
 
-//TODO(alb): finish the random order version (or round robin)
+/// Handler for the round-robin route. Rotates through the healthy members of the pool, one per
+/// request, and only returns the fallback if nothing else is healthy
+async fn handle_round_robin(
+    q: Query<QueryParams>,
+    State(state): State<healthcheck::HealthTable>,
+    State(metrics): State<Metrics>,
+) -> (StatusCode, String) {
+    let state = &mut state.lock();
+    let map = match state {
+        Ok(m) => m,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".into(),
+            )
+        }
+    };
 
-/// Handler for the random-member route. Returns a random selection from the healthy members
-// async fn handle_random_order(
-//     q: Query<QueryParams>,
-//     State(state): State<Arc<Mutex<HashMap<String, Vec<healthcheck::Member>>>>>,
-// ) -> (StatusCode, String) {
-//     let state = &state.lock();
-//
-//     let map = match state {
-//         Ok(m) => m,
-//         Err(_) => {
-//             return (
-//                 StatusCode::INTERNAL_SERVER_ERROR,
-//                 "
-//
-//         }
-//     }
-// }
+    match map.get_mut(&q.name) {
+        Some(p) => {
+            let unknown = p.health_unknown();
+            let member = selection::round_robin(p, q.family());
+            metrics.lookup(&q.name, "round-robin", served_fallback(member));
+            member_response(member, unknown)
+        }
+        None => (StatusCode::NOT_FOUND, "Pool not found".into()),
+    }
+}
 
-/// Set the contents of the "localhost" entry of the host:ip map to be some
-/// arbitrary IP to prove that the state is changing
-async fn reset(
+/// Handler for the addresses route. Returns every active address of the pool, one per line, or
+/// the fallback if nothing else is healthy
+async fn handle_addresses(
     q: Query<QueryParams>,
     State(state): State<healthcheck::HealthTable>,
+    State(metrics): State<Metrics>,
 ) -> (StatusCode, String) {
-    state.lock().unwrap().insert(
-        q.name.clone(),
-        vec![healthcheck::Member {
-            host: String::from("localhost"),
-            ip: Into::into([1, 2, 3, 4]),
-            healthy: true,
-            cancel: false,
-        }],
-    );
+    let map = match state.lock() {
+        Ok(m) => m,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".into(),
+            )
+        }
+    };
+
+    match map.get(&q.name) {
+        Some(p) => {
+            let active = selection::active(p, q.family());
+            metrics.lookup(
+                &q.name,
+                "addresses",
+                served_fallback(active.first().copied()),
+            );
+            if active.is_empty() {
+                return member_response(None, p.health_unknown());
+            }
+            let ips: Vec<String> = active.iter().map(|m| m.ip.to_string()).collect();
+            (StatusCode::OK, ips.join("\n"))
+        }
+        None => (StatusCode::NOT_FOUND, "Pool not found".into()),
+    }
+}
+
+/// Turn the result of a member selection into a lookup response. `unknown` tells a pool whose
+/// members haven't been probed yet apart from one which is down.
+fn member_response(member: Option<&healthcheck::Member>, unknown: bool) -> (StatusCode, String) {
+    match member {
+        Some(m) => (StatusCode::OK, m.ip.to_string()),
+        None if unknown => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Pool health unknown".into(),
+        ),
+        None => (
+            StatusCode::NOT_FOUND,
+            "No healthy members and no fallback IP".into(),
+        ),
+    }
+}
 
-    (StatusCode::OK, String::from("OK"))
+/// Whether a lookup was answered with the fallback IP
+fn served_fallback(member: Option<&healthcheck::Member>) -> bool {
+    member.is_some_and(|m| m.is_fallback())
 }
 
 /// Dump the entire state table to a JSON-formatted response
@@ -302,27 +524,24 @@ async fn dump_table(State(state): State<healthcheck::HealthTable>) -> (StatusCod
     (StatusCode::OK, serde_json::to_string(map).unwrap())
 }
 
-/// Reload the config and restart the pollers
-async fn reload(State(state): State<healthcheck::HealthTable>) -> (StatusCode, String) {
-    let mut pools = state.lock().unwrap();
-    for (_pool, members) in pools.iter_mut() {
-        for member in members {
-            member.cancel = true;
-        }
-    }
-
-    (StatusCode::OK, String::from("Config reloaded"))
+/// Probe and lookup metrics, and the health of every member, in the Prometheus text format
+async fn handle_metrics(
+    State(state): State<healthcheck::HealthTable>,
+    State(metrics): State<Metrics>,
+) -> (StatusCode, String) {
+    (StatusCode::OK, metrics.render(&state))
 }
 
-/// Read config file
-fn read_config(path: String) -> Result<Config, Box<dyn Error>> {
-    // let mut conf: Vec<gtm::Pool> = Vec::new();
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let conf: Config = serde_json::from_reader(reader)?;
-
-    Ok(conf)
+/// Re-read the config file and apply the changes. Members which are still configured keep their
+/// health. Returns a JSON report of what changed.
+async fn reload(State(reloader): State<SupervisorHandle>) -> (StatusCode, String) {
+    match reloader.reload().await {
+        Ok(report) => (StatusCode::OK, serde_json::to_string(&report).unwrap()),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Config reload failed: {e}"),
+        ),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,338 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::healthcheck::{FailureReason, Health, HealthTable, Pool, ProbeResult};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// Upper bounds, in seconds, of the probe duration histogram buckets
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket. Cumulative counts are worked out when rendering.
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = DURATION_BUCKETS.iter().position(|b| value <= *b) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Values {
+    /// Keyed by pool, host and result
+    probes: BTreeMap<(String, String, &'static str), u64>,
+    /// Keyed by poll type
+    durations: BTreeMap<&'static str, Histogram>,
+    /// Keyed by pool, host and new health
+    transitions: BTreeMap<(String, String, &'static str), u64>,
+    /// Keyed by pool and route
+    lookups: BTreeMap<(String, &'static str), u64>,
+    /// Keyed by pool and route
+    fallbacks: BTreeMap<(String, &'static str), u64>,
+}
+
+/// Probe and lookup counts from the pollers, the DNS server and the HTTP handlers, served in the
+/// Prometheus text format on `/metrics`
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<Values>>);
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Count a probe of a member and record how long it took
+    pub fn probe(&self, pool: &Pool, host: &str, result: &ProbeResult) {
+        let mut values = self.0.lock().unwrap();
+        let label = result.failure.map_or("success", FailureReason::as_str);
+        *values
+            .probes
+            .entry((pool.name.clone(), host.to_string(), label))
+            .or_default() += 1;
+        // Nothing was sent to the member if it couldn't be resolved
+        if result.failure != Some(FailureReason::DnsError) {
+            values
+                .durations
+                .entry(pool.poll_type.as_str())
+                .or_default()
+                .observe(result.response_ms as f64 / 1000.0);
+        }
+    }
+
+    /// Count a change in a member's health
+    pub fn transition(&self, pool: &str, host: &str, health: Health) {
+        let to = match health {
+            Health::Up => "up",
+            Health::Down => "down",
+            Health::Unknown => "unknown",
+        };
+        let mut values = self.0.lock().unwrap();
+        *values
+            .transitions
+            .entry((pool.to_string(), host.to_string(), to))
+            .or_default() += 1;
+    }
+
+    /// Count a lookup of a pool through `route`, and whether the fallback IP was served
+    pub fn lookup(&self, pool: &str, route: &'static str, fallback: bool) {
+        let mut values = self.0.lock().unwrap();
+        *values.lookups.entry((pool.to_string(), route)).or_default() += 1;
+        if fallback {
+            *values
+                .fallbacks
+                .entry((pool.to_string(), route))
+                .or_default() += 1;
+        }
+    }
+
+    /// Drop the series of a pool which was removed
+    pub fn remove_pool(&self, pool: &str) {
+        let mut values = self.0.lock().unwrap();
+        values.probes.retain(|(p, _, _), _| p != pool);
+        values.transitions.retain(|(p, _, _), _| p != pool);
+        values.lookups.retain(|(p, _), _| p != pool);
+        values.fallbacks.retain(|(p, _), _| p != pool);
+    }
+
+    /// Drop the series of a member which was removed from a pool
+    pub fn remove_member(&self, pool: &str, host: &str) {
+        let mut values = self.0.lock().unwrap();
+        values.probes.retain(|(p, h, _), _| p != pool || h != host);
+        values
+            .transitions
+            .retain(|(p, h, _), _| p != pool || h != host);
+    }
+
+    /// Render every metric, plus the current health of each member, in the Prometheus text
+    /// format
+    pub fn render(&self, cache: &HealthTable) -> String {
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP health_checker_member_up Whether the member is healthy (1) or not (0).\n",
+        );
+        out.push_str("# TYPE health_checker_member_up gauge\n");
+        {
+            let pools = cache.lock().unwrap();
+            let mut names: Vec<&String> = pools.keys().collect();
+            names.sort();
+            for name in names {
+                for m in pools[name].members.iter().filter(|m| !m.is_fallback()) {
+                    let _ = writeln!(
+                        out,
+                        "health_checker_member_up{{pool=\"{}\",host=\"{}\",ip=\"{}\"}} {}",
+                        escape(name),
+                        escape(&m.host),
+                        m.ip,
+                        u8::from(m.is_up())
+                    );
+                }
+            }
+        }
+
+        let values = self.0.lock().unwrap();
+
+        out.push_str("# HELP health_checker_probes_total Probes sent, by result.\n");
+        out.push_str("# TYPE health_checker_probes_total counter\n");
+        for ((pool, host, result), n) in &values.probes {
+            let _ = writeln!(
+                out,
+                "health_checker_probes_total{{pool=\"{}\",host=\"{}\",result=\"{}\"}} {}",
+                escape(pool),
+                escape(host),
+                result,
+                n
+            );
+        }
+
+        out.push_str("# HELP health_checker_probe_duration_seconds Time taken by each probe.\n");
+        out.push_str("# TYPE health_checker_probe_duration_seconds histogram\n");
+        for (poll_type, h) in &values.durations {
+            let mut cumulative = 0;
+            for (bound, n) in DURATION_BUCKETS.iter().zip(h.buckets) {
+                cumulative += n;
+                let _ = writeln!(
+                    out,
+                    "health_checker_probe_duration_seconds_bucket{{poll_type=\"{}\",le=\"{}\"}} {}",
+                    poll_type, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "health_checker_probe_duration_seconds_bucket{{poll_type=\"{}\",le=\"+Inf\"}} {}",
+                poll_type, h.count
+            );
+            let _ = writeln!(
+                out,
+                "health_checker_probe_duration_seconds_sum{{poll_type=\"{}\"}} {}",
+                poll_type, h.sum
+            );
+            let _ = writeln!(
+                out,
+                "health_checker_probe_duration_seconds_count{{poll_type=\"{}\"}} {}",
+                poll_type, h.count
+            );
+        }
+
+        out.push_str(
+            "# HELP health_checker_member_transitions_total Changes in member health, by new state.\n",
+        );
+        out.push_str("# TYPE health_checker_member_transitions_total counter\n");
+        for ((pool, host, to), n) in &values.transitions {
+            let _ = writeln!(
+                out,
+                "health_checker_member_transitions_total{{pool=\"{}\",host=\"{}\",to=\"{}\"}} {}",
+                escape(pool),
+                escape(host),
+                to,
+                n
+            );
+        }
+
+        out.push_str("# HELP health_checker_lookups_total Lookups of a pool, by route.\n");
+        out.push_str("# TYPE health_checker_lookups_total counter\n");
+        for ((pool, route), n) in &values.lookups {
+            let _ = writeln!(
+                out,
+                "health_checker_lookups_total{{pool=\"{}\",route=\"{}\"}} {}",
+                escape(pool),
+                route,
+                n
+            );
+        }
+
+        out.push_str(
+            "# HELP health_checker_fallback_served_total Lookups answered with the fallback IP.\n",
+        );
+        out.push_str("# TYPE health_checker_fallback_served_total counter\n");
+        for ((pool, route), n) in &values.fallbacks {
+            let _ = writeln!(
+                out,
+                "health_checker_fallback_served_total{{pool=\"{}\",route=\"{}\"}} {}",
+                escape(pool),
+                route,
+                n
+            );
+        }
+
+        out
+    }
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::healthcheck::Member;
+    use crate::test_util::{member, pool, table};
+
+    fn probe(response_ms: u64, failure: Option<FailureReason>) -> ProbeResult {
+        ProbeResult {
+            checked_at: 0,
+            response_ms,
+            status_code: None,
+            failure,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn renders_prometheus_text() {
+        let metrics = Metrics::new();
+        let pool = pool("app", 80, &["a"]);
+        metrics.probe(&pool, "a", &probe(3, None));
+        metrics.probe(&pool, "a", &probe(700, Some(FailureReason::Refused)));
+        metrics.probe(&pool, "a", &probe(0, Some(FailureReason::DnsError)));
+        metrics.transition("app", "a", Health::Down);
+        metrics.lookup("app", "info", false);
+        metrics.lookup("app", "dns", true);
+        metrics.lookup("app", "dns", true);
+
+        let cache = table(
+            "app",
+            vec![
+                member("a", [10, 0, 0, 1], Health::Up),
+                Member::fallback([10, 0, 0, 9].into()),
+            ],
+        );
+        let text = metrics.render(&cache);
+        let lines: Vec<&str> = text.lines().collect();
+
+        for expected in [
+            r#"health_checker_member_up{pool="app",host="a",ip="10.0.0.1"} 1"#,
+            r#"health_checker_probes_total{pool="app",host="a",result="success"} 1"#,
+            r#"health_checker_probes_total{pool="app",host="a",result="refused"} 1"#,
+            r#"health_checker_probes_total{pool="app",host="a",result="dns_error"} 1"#,
+            r#"health_checker_probe_duration_seconds_bucket{poll_type="TCP",le="0.005"} 1"#,
+            r#"health_checker_probe_duration_seconds_bucket{poll_type="TCP",le="0.5"} 1"#,
+            r#"health_checker_probe_duration_seconds_bucket{poll_type="TCP",le="1"} 2"#,
+            r#"health_checker_probe_duration_seconds_bucket{poll_type="TCP",le="+Inf"} 2"#,
+            r#"health_checker_probe_duration_seconds_count{poll_type="TCP"} 2"#,
+            r#"health_checker_member_transitions_total{pool="app",host="a",to="down"} 1"#,
+            r#"health_checker_lookups_total{pool="app",route="dns"} 2"#,
+            r#"health_checker_lookups_total{pool="app",route="info"} 1"#,
+            r#"health_checker_fallback_served_total{pool="app",route="dns"} 2"#,
+        ] {
+            assert!(lines.contains(&expected), "missing {expected} in\n{text}");
+        }
+        // The fallback isn't a member
+        assert!(!text.contains("10.0.0.9"));
+        assert!(!text.contains(r#"fallback_served_total{pool="app",route="info"}"#));
+    }
+
+    #[test]
+    fn forgets_removed_pools_and_members() {
+        let metrics = Metrics::new();
+        let pool = pool("app", 80, &["a", "b"]);
+        for host in ["a", "b"] {
+            metrics.probe(&pool, host, &probe(3, None));
+            metrics.transition("app", host, Health::Up);
+        }
+        metrics.lookup("app", "info", true);
+        let cache = table("app", vec![member("b", [10, 0, 0, 2], Health::Up)]);
+
+        metrics.remove_member("app", "a");
+        let text = metrics.render(&cache);
+        assert!(!text.contains(r#"host="a""#), "{text}");
+        assert!(text.contains(r#"probes_total{pool="app",host="b",result="success"} 1"#));
+
+        metrics.remove_pool("app");
+        let text = metrics.render(&table("other", vec![]));
+        assert!(!text.contains(r#"pool="app""#), "{text}");
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::healthcheck::{HealthTable, Member, PoolHealth};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio::time;
+
+const DEFAULT_INTERVAL: u64 = 30;
+
+#[derive(Clone, Deserialize)]
+///Configuration for saving the health table to disk
+pub struct PersistOptions {
+    pub path: PathBuf,
+    /// Seconds between snapshots
+    pub interval: Option<u64>,
+}
+
+pub type Snapshot = HashMap<String, PoolHealth>;
+
+/// Write the health table to `path`, atomically
+pub fn save(path: &Path, cache: &HealthTable) -> Result<(), Box<dyn Error>> {
+    let snapshot = cache.lock().map_err(|e| e.to_string())?.clone();
+    write_atomic(path, &serde_json::to_vec(&snapshot)?)?;
+    Ok(())
+}
+
+/// Write `contents` to a temporary file next to `path` and then rename it over `path`, so a
+/// crash part way through never leaves a truncated file. The file keeps the permissions of the one
+/// it replaces, since the config file may hold credentials.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let file = File::create(&tmp)?;
+    match fs::metadata(path) {
+        Ok(existing) => file.set_permissions(existing.permissions())?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    let mut writer = BufWriter::new(file);
+    writer.write_all(contents)?;
+    writer.flush()?;
+    // The contents must be on disk before the rename is, or a crash could leave an empty file
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    fs::rename(&tmp, path)
+}
+
+/// Read a snapshot written by `save`
+pub fn load(path: &Path) -> Result<Snapshot, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let snapshot: Snapshot = serde_json::from_reader(reader)?;
+    Ok(snapshot)
+}
+
+/// Copy the last known health of each member from a snapshot into the health table. Only members
+/// which are still configured are restored. Pools and members which were added since the snapshot
+/// was taken keep their initial state.
+pub fn restore(cache: &HealthTable, snapshot: &Snapshot) {
+    let mut pools = cache.lock().unwrap();
+    for (name, pool) in pools.iter_mut() {
+        let saved = match snapshot.get(name) {
+            Some(s) => s,
+            None => continue,
+        };
+        for member in pool.members.iter_mut().filter(|m| !m.is_fallback()) {
+            // Hosts with a member per resolved address are matched by address as well. Otherwise
+            // the host's only saved member is used, even if its address has changed since.
+            let same_host: Vec<&Member> = saved
+                .members
+                .iter()
+                .filter(|s| s.host == member.host)
+                .collect();
+            let found = match same_host.iter().find(|s| s.ip == member.ip) {
+                Some(s) => Some(*s),
+                None if same_host.len() == 1 => Some(same_host[0]),
+                None => None,
+            };
+            if let Some(s) = found {
+                member.health = s.health;
+                member.consecutive_successes = s.consecutive_successes;
+                member.consecutive_failures = s.consecutive_failures;
+                member.last_transition = s.last_transition;
+                member.last_probe = s.last_probe.clone();
+                member.admin = s.admin.clone();
+                // Keep the last known address if the host couldn't be resolved on startup
+                if member.ip.is_unspecified() {
+                    member.ip = s.ip;
+                }
+            }
+        }
+    }
+}
+
+/// Long lived task which saves the health table every interval
+pub async fn snapshot_loop(options: PersistOptions, cache: HealthTable) {
+    let interval = options.interval.unwrap_or(DEFAULT_INTERVAL);
+    info!(
+        "Saving health state to {} every {} seconds",
+        options.path.display(),
+        interval
+    );
+    loop {
+        time::sleep(time::Duration::from_secs(interval)).await;
+        if let Err(e) = save(&options.path, &cache) {
+            warn!(
+                "Failed to save health state to {}: {e}",
+                options.path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::healthcheck::Health;
+    use crate::test_util::{member, table};
+
+    #[test]
+    fn restores_saved_health() {
+        let path = std::env::temp_dir().join(format!("health_checker_{}.json", std::process::id()));
+
+        let saved = table(
+            "app",
+            vec![
+                Member {
+                    consecutive_failures: 3,
+                    ..member("a", [127, 0, 0, 2], Health::Down)
+                },
+                member("b", [127, 0, 0, 3], Health::Up),
+            ],
+        );
+        save(&path, &saved).unwrap();
+        let snapshot = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let fresh = table(
+            "app",
+            vec![
+                member("a", [0, 0, 0, 0], Health::Up),
+                member("c", [127, 0, 0, 4], Health::Up),
+            ],
+        );
+        restore(&fresh, &snapshot);
+
+        let pools = fresh.lock().unwrap();
+        let members = &pools["app"].members;
+        assert_eq!(members[0].health, Health::Down);
+        assert_eq!(members[0].consecutive_failures, 3);
+        assert_eq!(members[0].ip, std::net::Ipv4Addr::new(127, 0, 0, 2));
+        assert!(members[1].is_up());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_writes_keep_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("health_checker_perm_{}", std::process::id()));
+        fs::write(&path, "secret").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_atomic(&path, b"new secret").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new secret");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use tokio::task::Id;
+use tokio_util::sync::CancellationToken;
+
+/// Pool name and member host of a poller
+pub type PollerKey = (String, String);
+
+/// Cancellation tokens for the running pollers.
+///
+/// Each member's token is a child of its pool's token, which is a child of a root token, so a
+/// single member, a whole pool or every poller can be stopped at once. Pollers stop as soon as
+/// their token is cancelled, even in the middle of a probe.
+#[derive(Default)]
+pub struct PollerRegistry {
+    root: CancellationToken,
+    pools: HashMap<String, CancellationToken>,
+    members: HashMap<PollerKey, (CancellationToken, Id)>,
+}
+
+impl PollerRegistry {
+    pub fn new() -> PollerRegistry {
+        PollerRegistry::default()
+    }
+
+    /// Create a token for a new poller in `pool`. Register the poller with `insert` once it has
+    /// been spawned.
+    pub fn token(&mut self, pool: &str) -> CancellationToken {
+        let root = &self.root;
+        self.pools
+            .entry(pool.to_string())
+            .or_insert_with(|| root.child_token())
+            .child_token()
+    }
+
+    /// Register the poller for a member. Any poller already registered for the member is stopped.
+    pub fn insert(&mut self, key: PollerKey, token: CancellationToken, task: Id) {
+        if let Some((old, _)) = self.members.insert(key, (token, task)) {
+            old.cancel();
+        }
+    }
+
+    /// Stop the poller for a single member. Returns false if it wasn't running.
+    pub fn stop_member(&mut self, key: &PollerKey) -> bool {
+        match self.members.remove(key) {
+            Some((token, _)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop every poller in a pool
+    pub fn stop_pool(&mut self, pool: &str) {
+        if let Some(token) = self.pools.remove(pool) {
+            token.cancel();
+        }
+        self.members.retain(|(p, _), _| p != pool);
+    }
+
+    /// Stop every poller. New pollers can still be registered afterwards.
+    pub fn stop_all(&mut self) {
+        let root = std::mem::take(&mut self.root);
+        root.cancel();
+        self.pools.clear();
+        self.members.clear();
+    }
+
+    /// The task currently polling a member
+    pub fn task(&self, key: &PollerKey) -> Option<Id> {
+        self.members.get(key).map(|(_, id)| *id)
+    }
+
+    /// Whether `task` is the registered poller for `key`, rather than one which has been stopped
+    /// or replaced
+    pub fn is_current(&self, key: &PollerKey, task: Id) -> bool {
+        self.task(key) == Some(task)
+    }
+
+    /// Number of registered pollers
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(pool: &str, host: &str) -> PollerKey {
+        (pool.into(), host.into())
+    }
+
+    #[tokio::test]
+    async fn stops_members_pools_and_everything() {
+        let mut registry = PollerRegistry::new();
+        let mut tokens = HashMap::new();
+        for k in [key("a", "1"), key("a", "2"), key("b", "3"), key("c", "4")] {
+            let token = registry.token(&k.0);
+            let id = tokio::spawn(async {}).id();
+            registry.insert(k.clone(), token.clone(), id);
+            tokens.insert(k, token);
+        }
+        assert_eq!(registry.len(), 4);
+
+        assert!(registry.stop_member(&key("a", "1")));
+        assert!(tokens[&key("a", "1")].is_cancelled());
+        assert!(!tokens[&key("a", "2")].is_cancelled());
+        assert!(!registry.stop_member(&key("a", "1")));
+
+        registry.stop_pool("a");
+        assert!(tokens[&key("a", "2")].is_cancelled());
+        assert!(!tokens[&key("b", "3")].is_cancelled());
+
+        registry.stop_all();
+        assert!(tokens.values().all(|t| t.is_cancelled()));
+        assert!(registry.is_empty());
+
+        // Pollers registered after stop_all aren't born cancelled
+        assert!(!registry.token("a").is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn replacing_a_poller_stops_the_old_one() {
+        let mut registry = PollerRegistry::new();
+        let old = registry.token("a");
+        let old_id = tokio::spawn(async {}).id();
+        registry.insert(key("a", "1"), old.clone(), old_id);
+
+        let new = registry.token("a");
+        let new_id = tokio::spawn(async {}).id();
+        registry.insert(key("a", "1"), new.clone(), new_id);
+
+        assert!(old.is_cancelled());
+        assert!(!new.is_cancelled());
+        assert!(!registry.is_current(&key("a", "1"), old_id));
+        assert!(registry.is_current(&key("a", "1"), new_id));
+    }
+}
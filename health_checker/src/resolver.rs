@@ -0,0 +1,168 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::healthcheck::AddressFamily;
+use hickory_resolver::config::{
+    LookupIpStrategy, NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig,
+    ResolverOpts,
+};
+use hickory_resolver::TokioAsyncResolver;
+use log::{info, warn};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time;
+
+const DEFAULT_TIMEOUT: u64 = 5;
+
+#[derive(Clone, Default, Deserialize)]
+///Configuration for resolving member hostnames
+pub struct ResolverOptions {
+    /// Nameservers to query instead of the ones in /etc/resolv.conf
+    #[serde(default)]
+    pub nameservers: Vec<SocketAddr>,
+    /// Seconds to wait for a lookup before it fails
+    pub timeout: Option<u64>,
+    /// Number of records to cache. Cached records are kept for their TTL.
+    pub cache_size: Option<usize>,
+}
+
+#[derive(Clone)]
+/// Async resolver for member hostnames, with one answer cache for every poller
+pub struct Resolver {
+    inner: TokioAsyncResolver,
+    timeout: Duration,
+}
+
+impl Resolver {
+    /// Build a resolver from the config. Without nameservers in the config the system resolver
+    /// config is used, and the hosts file is consulted either way.
+    pub fn new(options: &ResolverOptions) -> Resolver {
+        let timeout = Duration::from_secs(options.timeout.unwrap_or(DEFAULT_TIMEOUT));
+
+        let (config, mut opts) = match options.nameservers.is_empty() {
+            false => {
+                let mut group = NameServerConfigGroup::new();
+                for ns in &options.nameservers {
+                    group.push(NameServerConfig::new(*ns, Protocol::Udp));
+                    group.push(NameServerConfig::new(*ns, Protocol::Tcp));
+                }
+                info!("Resolving members with {:?}", options.nameservers);
+                (
+                    ResolverConfig::from_parts(None, vec![], group),
+                    ResolverOpts::default(),
+                )
+            }
+            true => match hickory_resolver::system_conf::read_system_conf() {
+                Ok(conf) => conf,
+                Err(e) => {
+                    warn!("Unable to read the system resolver config, using defaults: {e}");
+                    (ResolverConfig::default(), ResolverOpts::default())
+                }
+            },
+        };
+        opts.timeout = timeout;
+        // Both families are looked up and then filtered per pool
+        opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+        opts.use_hosts_file = true;
+        if let Some(size) = options.cache_size {
+            opts.cache_size = size;
+        }
+
+        Resolver {
+            inner: TokioAsyncResolver::tokio(config, opts),
+            timeout,
+        }
+    }
+
+    /// Resolve `host` to every address of the requested family, without duplicates. IP literals
+    /// are returned as is.
+    pub async fn lookup(
+        &self,
+        host: &str,
+        port: u16,
+        family: AddressFamily,
+    ) -> Result<Vec<SocketAddr>, String> {
+        let found = match time::timeout(self.timeout, self.inner.lookup_ip(host)).await {
+            Ok(Ok(found)) => found,
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err(format!("timed out after {:?}", self.timeout)),
+        };
+
+        let mut addrs: Vec<SocketAddr> = Vec::new();
+        for ip in found.iter().filter(|ip| family.matches(ip)) {
+            let addr = SocketAddr::new(ip, port);
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        if addrs.is_empty() {
+            return Err(format!("no {:?} addresses found", family));
+        }
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns;
+    use crate::healthcheck::Health;
+    use crate::metrics::Metrics;
+    use crate::test_util::{member, table};
+    use tokio::net::UdpSocket;
+
+    #[tokio::test]
+    async fn literals_are_filtered_by_family() {
+        let resolver = Resolver::new(&ResolverOptions::default());
+
+        let addrs = resolver
+            .lookup("127.0.0.2", 80, AddressFamily::Any)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["127.0.0.2:80".parse().unwrap()]);
+        assert!(resolver
+            .lookup("::1", 80, AddressFamily::Ipv4)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_configured_nameservers() {
+        // Use the built-in DNS server as the nameserver
+        let cache = table(
+            "app.example.com",
+            vec![member("a", [127, 0, 0, 9], Health::Up)],
+        );
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(dns::serve_udp(server, cache, Metrics::new(), 5));
+
+        let resolver = Resolver::new(&ResolverOptions {
+            nameservers: vec![addr],
+            timeout: Some(2),
+            cache_size: None,
+        });
+        let addrs = resolver
+            .lookup("app.example.com", 443, AddressFamily::Ipv4)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["127.0.0.9:443".parse().unwrap()]);
+
+        assert!(resolver
+            .lookup("nope.example.com", 443, AddressFamily::Ipv4)
+            .await
+            .is_err());
+    }
+}
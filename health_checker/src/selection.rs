@@ -0,0 +1,374 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::healthcheck::{AddressFamily, Member, PoolHealth};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// How a pool picks which healthy member to answer with
+pub enum LbMethod {
+    /// First healthy member in configured order
+    #[default]
+    Priority,
+    /// Rotate through the healthy members
+    RoundRobin,
+    /// Uniformly random healthy member
+    Random,
+    /// Random healthy member, in proportion to member weight
+    Weighted,
+    /// Rotate through the healthy members, in proportion to member weight
+    Ratio,
+}
+
+/// Select a member of the pool with an address of `family`, using the pool's configured load
+/// balancing method.
+pub fn select(pool: &mut PoolHealth, family: AddressFamily) -> Option<&Member> {
+    select_with(pool, pool.lb_method, family)
+}
+
+/// Select a member of the pool with an address of `family`, using `method` instead of the pool's
+/// configured load balancing method.
+pub fn select_with(
+    pool: &mut PoolHealth,
+    method: LbMethod,
+    family: AddressFamily,
+) -> Option<&Member> {
+    match method {
+        LbMethod::Priority => priority(pool, family),
+        LbMethod::RoundRobin => round_robin(pool, family),
+        LbMethod::Random => random(pool, family),
+        LbMethod::Weighted => weighted_random(pool, family),
+        LbMethod::Ratio => weighted_round_robin(pool, family),
+    }
+}
+
+/// Split a pool's members into the active members and the fallback member.
+///
+/// Only members which are up, enabled and have a resolved address of `family` are active. The
+/// fallback is left out while the pool's health is unknown.
+///
+/// Healthy members are taken one priority group at a time, highest priority first, until at
+/// least `min_active` members have been collected. With the default of one, only the highest
+/// priority group with any healthy member is active. The active members are ordered by priority
+/// group and then by configured order.
+fn candidates(pool: &PoolHealth, family: AddressFamily) -> (Vec<&Member>, Option<&Member>) {
+    let mut healthy: Vec<&Member> = pool
+        .members
+        .iter()
+        .filter(|m| m.is_up() && m.is_enabled() && !m.is_fallback() && family.matches(&m.ip))
+        // Members whose host hasn't been resolved yet have nothing to serve
+        .filter(|m| !m.ip.is_unspecified())
+        .collect();
+    // Stable sort, so members within a group keep their configured order
+    healthy.sort_by_key(|m| std::cmp::Reverse(m.priority));
+
+    let min_active = pool.min_active.max(1);
+    let mut active = 0;
+    while active < healthy.len() && active < min_active {
+        let group = healthy[active].priority;
+        active += healthy[active..]
+            .iter()
+            .take_while(|m| m.priority == group)
+            .count();
+    }
+    healthy.truncate(active);
+
+    // Don't send traffic to the fallback just because the members haven't been probed yet
+    let fallback = match pool.health_unknown() {
+        true => None,
+        false => pool
+            .members
+            .iter()
+            .find(|m| m.is_fallback() && family.matches(&m.ip)),
+    };
+    (healthy, fallback)
+}
+
+/// Every active member with an address of `family`, or just the fallback if nothing else is
+/// healthy.
+pub fn active(pool: &PoolHealth, family: AddressFamily) -> Vec<&Member> {
+    let (healthy, fallback) = candidates(pool, family);
+    match healthy.is_empty() {
+        true => fallback.into_iter().collect(),
+        false => healthy,
+    }
+}
+
+/// Return the pool's current rotation position and move it on by one.
+fn advance_cursor(pool: &mut PoolHealth) -> usize {
+    let cursor = pool.cursor;
+    pool.cursor = cursor.wrapping_add(1);
+    cursor
+}
+
+/// Select the first active member, or the fallback if nothing else is healthy.
+pub fn priority(pool: &PoolHealth, family: AddressFamily) -> Option<&Member> {
+    let (healthy, fallback) = candidates(pool, family);
+    healthy.first().copied().or(fallback)
+}
+
+/// Select a random active member, or the fallback if nothing else is healthy.
+pub fn random(pool: &PoolHealth, family: AddressFamily) -> Option<&Member> {
+    let (healthy, fallback) = candidates(pool, family);
+    healthy
+        .choose(&mut rand::thread_rng())
+        .copied()
+        .or(fallback)
+}
+
+/// Select the next active member in rotation, or the fallback if nothing else is healthy. The
+/// pool's cursor is advanced on every selection.
+pub fn round_robin(pool: &mut PoolHealth, family: AddressFamily) -> Option<&Member> {
+    let cursor = advance_cursor(pool);
+    let (healthy, fallback) = candidates(pool, family);
+    if healthy.is_empty() {
+        return fallback;
+    }
+    Some(healthy[cursor % healthy.len()])
+}
+
+/// Select a random active member in proportion to its weight, or the fallback if nothing else
+/// is healthy. Members with a weight of 0 are never selected.
+pub fn weighted_random(pool: &PoolHealth, family: AddressFamily) -> Option<&Member> {
+    let (healthy, fallback) = candidates(pool, family);
+    healthy
+        .choose_weighted(&mut rand::thread_rng(), |m| m.weight)
+        .ok()
+        .copied()
+        .or(fallback)
+}
+
+/// Select the next active member in a rotation where each member appears as many times as its
+/// weight, or the fallback if nothing else is healthy. Members with a weight of 0 are never
+/// selected.
+pub fn weighted_round_robin(pool: &mut PoolHealth, family: AddressFamily) -> Option<&Member> {
+    let cursor = advance_cursor(pool);
+    let (healthy, fallback) = candidates(pool, family);
+    let total: usize = healthy.iter().map(|m| m.weight as usize).sum();
+    if total == 0 {
+        return fallback;
+    }
+
+    let mut position = cursor % total;
+    for member in healthy {
+        let weight = member.weight as usize;
+        if position < weight {
+            return Some(member);
+        }
+        position -= weight;
+    }
+    fallback
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::healthcheck::Health;
+    use crate::test_util::member;
+
+    const ANY: AddressFamily = AddressFamily::Any;
+
+    fn pool() -> PoolHealth {
+        PoolHealth::new(vec![
+            member("a", [127, 0, 0, 2], Health::Down),
+            member("b", [127, 0, 0, 3], Health::Up),
+            member("c", [127, 0, 0, 4], Health::Up),
+            Member::fallback([127, 0, 0, 1].into()),
+        ])
+    }
+
+    fn hosts(
+        n: usize,
+        pool: &mut PoolHealth,
+        f: fn(&mut PoolHealth, AddressFamily) -> Option<&Member>,
+    ) -> Vec<String> {
+        (0..n).map(|_| f(pool, ANY).unwrap().host.clone()).collect()
+    }
+
+    #[test]
+    fn priority_skips_unhealthy_members() {
+        assert_eq!(priority(&pool(), ANY).unwrap().host, "b");
+    }
+
+    #[test]
+    fn random_never_picks_unhealthy_or_fallback() {
+        let p = pool();
+        for _ in 0..100 {
+            let host = &random(&p, ANY).unwrap().host;
+            assert!(host == "b" || host == "c");
+        }
+    }
+
+    #[test]
+    fn round_robin_rotates_through_healthy_members() {
+        assert_eq!(hosts(4, &mut pool(), round_robin), vec!["b", "c", "b", "c"]);
+    }
+
+    #[test]
+    fn select_follows_pool_method() {
+        let mut p = pool();
+        assert_eq!(select(&mut p, ANY).unwrap().host, "b");
+        assert_eq!(select(&mut p, ANY).unwrap().host, "b");
+
+        p.lb_method = LbMethod::RoundRobin;
+        assert_eq!(select(&mut p, ANY).unwrap().host, "b");
+        assert_eq!(select(&mut p, ANY).unwrap().host, "c");
+    }
+
+    #[test]
+    fn weighted_round_robin_follows_weights() {
+        let mut p = pool();
+        p.members[1].weight = 7;
+        p.members[2].weight = 3;
+        let picks = hosts(100, &mut p, weighted_round_robin);
+        assert_eq!(picks.iter().filter(|h| *h == "b").count(), 70);
+        assert_eq!(picks.iter().filter(|h| *h == "c").count(), 30);
+    }
+
+    #[test]
+    fn zero_weight_members_are_never_picked() {
+        let mut p = pool();
+        p.members[1].weight = 0;
+        for _ in 0..50 {
+            assert_eq!(weighted_random(&p, ANY).unwrap().host, "c");
+            assert_eq!(weighted_round_robin(&mut p, ANY).unwrap().host, "c");
+        }
+
+        p.members[2].weight = 0;
+        assert!(weighted_random(&p, ANY).unwrap().is_fallback());
+        assert!(weighted_round_robin(&mut p, ANY).unwrap().is_fallback());
+    }
+
+    #[test]
+    fn highest_priority_group_takes_all_traffic() {
+        let mut p = pool();
+        p.members[0].health = Health::Up;
+        p.members[2].priority = 10;
+        assert_eq!(hosts(3, &mut p, round_robin), vec!["c", "c", "c"]);
+
+        p.members[2].health = Health::Down;
+        p.cursor = 0;
+        assert_eq!(hosts(2, &mut p, round_robin), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn lower_groups_join_below_min_active() {
+        let mut p = pool();
+        p.members[0].health = Health::Up;
+        p.members[0].priority = 10;
+        p.members[1].priority = 10;
+        p.min_active = 2;
+        assert_eq!(hosts(4, &mut p, round_robin), vec!["a", "b", "a", "b"]);
+
+        // One member of the top group goes down, so the next group is activated as well
+        p.members[1].health = Health::Down;
+        p.cursor = 0;
+        assert_eq!(hosts(2, &mut p, round_robin), vec!["a", "c"]);
+        assert_eq!(priority(&p, ANY).unwrap().host, "a");
+    }
+
+    #[test]
+    fn lb_method_parses_from_config() {
+        let method: LbMethod = serde_json::from_str("\"round_robin\"").unwrap();
+        assert_eq!(method, LbMethod::RoundRobin);
+    }
+
+    #[test]
+    fn fallback_only_when_nothing_healthy() {
+        let mut p = PoolHealth::new(vec![
+            member("a", [127, 0, 0, 2], Health::Down),
+            Member::fallback([127, 0, 0, 1].into()),
+        ]);
+        assert!(priority(&p, ANY).unwrap().is_fallback());
+        assert!(random(&p, ANY).unwrap().is_fallback());
+        assert!(round_robin(&mut p, ANY).unwrap().is_fallback());
+
+        let mut p = PoolHealth::new(vec![member("a", [127, 0, 0, 2], Health::Down)]);
+        assert!(priority(&p, ANY).is_none());
+        assert!(random(&p, ANY).is_none());
+        assert!(round_robin(&mut p, ANY).is_none());
+    }
+
+    #[test]
+    fn unknown_members_and_fallback_are_held_back() {
+        let mut p = pool();
+        p.members[1].health = Health::Unknown;
+        p.members[2].health = Health::Unknown;
+        assert!(p.health_unknown());
+        assert!(priority(&p, ANY).is_none());
+
+        p.members[2].health = Health::Up;
+        assert!(!p.health_unknown());
+        assert_eq!(priority(&p, ANY).unwrap().host, "c");
+    }
+
+    #[test]
+    fn family_filters_members_and_fallback() {
+        let mut p = pool();
+        p.members[2].ip = "2001:db8::4".parse().unwrap();
+        p.members
+            .push(Member::fallback("2001:db8::1".parse().unwrap()));
+
+        assert_eq!(priority(&p, AddressFamily::Ipv4).unwrap().host, "b");
+        assert_eq!(priority(&p, AddressFamily::Ipv6).unwrap().host, "c");
+
+        p.members[2].health = Health::Down;
+        let v6 = priority(&p, AddressFamily::Ipv6).unwrap();
+        assert!(v6.is_fallback());
+        assert!(v6.ip.is_ipv6());
+        assert!(priority(&p, AddressFamily::Ipv4).unwrap().ip.is_ipv4());
+    }
+
+    #[test]
+    fn active_lists_every_candidate() {
+        let mut p = pool();
+        p.members[0].health = Health::Up;
+        p.members[2].priority = 10;
+        p.min_active = 2;
+        let hosts: Vec<&str> = active(&p, ANY).iter().map(|m| m.host.as_str()).collect();
+        assert_eq!(hosts, vec!["c", "a", "b"]);
+
+        for m in p.members.iter_mut().filter(|m| !m.is_fallback()) {
+            m.health = Health::Down;
+        }
+        assert!(active(&p, ANY)[0].is_fallback());
+    }
+
+    #[test]
+    fn disabled_and_forced_offline_members_are_skipped() {
+        use crate::healthcheck::{AdminMode, AdminState};
+
+        let mut p = pool();
+        p.members[1].admin.state = AdminMode::Disabled;
+        assert_eq!(priority(&p, ANY).unwrap().host, "c");
+        // A drained member is still reported as up
+        assert!(p.members[1].is_up());
+
+        p.members[2].admin.state = AdminMode::ForcedOffline;
+        assert!(!p.members[2].is_up());
+        assert!(priority(&p, ANY).unwrap().is_fallback());
+
+        // Expired states no longer apply
+        p.members[2].admin = AdminState {
+            state: AdminMode::ForcedOffline,
+            reason: Some("maintenance".into()),
+            until: Some(1),
+        };
+        assert_eq!(priority(&p, ANY).unwrap().host, "c");
+        assert!(p.members[2].admin.expire());
+        assert_eq!(p.members[2].admin, AdminState::default());
+    }
+}
@@ -0,0 +1,777 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::{read_config, validate_pools, write_pool};
+use crate::events::{Event, Events};
+use crate::healthcheck::{self, HealthTable, Member, MemberConfig, PollType, Pool, PoolHealth};
+use crate::metrics::Metrics;
+use crate::persist::{self, Snapshot};
+use crate::registry::{PollerKey, PollerRegistry};
+use crate::resolver::Resolver;
+use futures_util::future::join_all;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{Id, JoinSet};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+/// Request for the supervisor, with the channel to send the outcome back on
+pub enum Request {
+    Reload(oneshot::Sender<Result<ReloadReport, String>>),
+    Change(Change, oneshot::Sender<Result<ReloadReport, ChangeError>>),
+}
+
+/// Change to the running pool config made through the API
+pub enum Change {
+    /// Add a pool, or replace the pool with the same name
+    PutPool(Pool),
+    DeletePool(String),
+    /// Add a member to a pool, or update the member with the same host
+    PutMember {
+        pool: String,
+        member: MemberConfig,
+    },
+    DeleteMember {
+        pool: String,
+        host: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+/// Why a change to the running pool config was rejected
+pub enum ChangeError {
+    PoolNotFound,
+    MemberNotFound,
+    /// The change would leave an invalid config
+    Invalid(String),
+    /// The change was applied, but couldn't be written to the config file
+    WriteFailed(String),
+    /// The supervisor has shut down
+    Unavailable,
+}
+
+impl fmt::Display for ChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChangeError::PoolNotFound => write!(f, "Pool not found"),
+            ChangeError::MemberNotFound => write!(f, "Member not found"),
+            ChangeError::Invalid(e) => write!(f, "Invalid config: {e}"),
+            ChangeError::WriteFailed(e) => {
+                write!(f, "Change applied, but writing the config file failed: {e}")
+            }
+            ChangeError::Unavailable => write!(f, "Health checker is not running"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+/// What a config reload, or a change through the API, changed
+pub struct ReloadReport {
+    pub added_pools: Vec<String>,
+    pub removed_pools: Vec<String>,
+    /// Pools whose probe settings changed, so all of their pollers were restarted
+    pub restarted_pools: Vec<String>,
+    /// Pools whose load balancing, minimum active members or fallback addresses changed
+    pub reconfigured_pools: Vec<String>,
+    pub added_members: BTreeMap<String, Vec<String>>,
+    pub removed_members: BTreeMap<String, Vec<String>>,
+    /// Members whose weight or priority changed
+    pub reconfigured_members: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Clone)]
+/// Cloneable handle for asking the supervisor to reload the config file or change pools
+pub struct SupervisorHandle(mpsc::Sender<Request>);
+
+impl SupervisorHandle {
+    /// Re-read the config file and apply it. An invalid config is rejected and the running
+    /// config is kept.
+    pub async fn reload(&self) -> Result<ReloadReport, String> {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .send(Request::Reload(tx))
+            .await
+            .map_err(|_| String::from("Health checker is not running"))?;
+        rx.await
+            .map_err(|_| String::from("Health checker is not running"))?
+    }
+
+    /// Apply a change to the running pool config. A change which would leave an invalid config
+    /// is rejected.
+    pub async fn change(&self, change: Change) -> Result<ReloadReport, ChangeError> {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .send(Request::Change(change, tx))
+            .await
+            .map_err(|_| ChangeError::Unavailable)?;
+        rx.await.map_err(|_| ChangeError::Unavailable)?
+    }
+}
+
+/// Long lived task which reloads the config every time the process receives SIGHUP. Failed
+/// reloads are logged by the supervisor and the running config is kept.
+pub async fn reload_on_sighup(reloader: SupervisorHandle) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Unable to listen for SIGHUP, reloads are only available from the API: {e}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading config");
+        let _ = reloader.reload().await;
+    }
+}
+
+/// How often the watched config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the config file has to stay unchanged before it's reloaded, so a reload doesn't pick
+/// up a file which is still being written
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Modification time and size of a file, or None if it can't be read
+fn fingerprint(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+/// Long lived task which reloads the config whenever the file changes. A change is only applied
+/// once the file has settled for `WATCH_DEBOUNCE`. A broken edit is logged by the supervisor and
+/// the last good config is kept.
+pub async fn reload_on_change(path: PathBuf, reloader: SupervisorHandle) {
+    info!("Watching {} for changes", path.display());
+    let mut last = fingerprint(&path);
+    let mut changed_at: Option<Instant> = None;
+    let mut ticker = time::interval(WATCH_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let current = fingerprint(&path);
+        if current != last {
+            last = current;
+            changed_at = Some(Instant::now());
+            continue;
+        }
+        if changed_at.is_some_and(|t| t.elapsed() >= WATCH_DEBOUNCE) {
+            changed_at = None;
+            if last.is_none() {
+                warn!(
+                    "Config file {} is missing, keeping the running config",
+                    path.display()
+                );
+                continue;
+            }
+            info!("Config file {} changed, reloading", path.display());
+            let _ = reloader.reload().await;
+        }
+    }
+}
+
+/// Owns the pollers and the running pool config. Keeps the health table and the pollers in step
+/// with the config as it's reloaded.
+pub struct Supervisor {
+    cache: HealthTable,
+    metrics: Metrics,
+    events: Events,
+    pools: HashMap<String, Arc<Pool>>,
+    join_set: JoinSet<()>,
+    pollers: PollerRegistry,
+    tasks: HashMap<Id, PollerKey>,
+    snapshot: Option<Snapshot>,
+    resolver: Resolver,
+    /// Config file to write changes made through the API back to
+    write_back: Option<PathBuf>,
+}
+
+impl Supervisor {
+    /// Create a supervisor with no pools. A saved snapshot, if given, is restored into the
+    /// health table the first time the config is applied.
+    pub fn new(
+        cache: HealthTable,
+        metrics: Metrics,
+        events: Events,
+        snapshot: Option<Snapshot>,
+        resolver: Resolver,
+    ) -> Supervisor {
+        Supervisor {
+            cache,
+            metrics,
+            events,
+            resolver,
+            pools: HashMap::new(),
+            join_set: JoinSet::new(),
+            pollers: PollerRegistry::new(),
+            tasks: HashMap::new(),
+            snapshot,
+            write_back: None,
+        }
+    }
+
+    /// Write changes made through the API back to the config file at `path`
+    pub fn write_back_to(&mut self, path: PathBuf) {
+        self.write_back = Some(path);
+    }
+
+    /// Bring the health table and pollers in line with `pools`. Members which exist in both the
+    /// running and the new config keep their health state.
+    pub async fn apply(&mut self, pools: Vec<Pool>) -> ReloadReport {
+        let mut report = ReloadReport::default();
+        let mut new_pools: HashMap<String, Arc<Pool>> = pools
+            .into_iter()
+            .map(|p| (p.name.clone(), Arc::new(p)))
+            .collect();
+
+        // Resolve new members before taking the lock on the table
+        let mut lookups = Vec::new();
+        for pool in new_pools.values() {
+            let old = self.pools.get(&pool.name);
+            for m in &pool.members {
+                if !old.is_some_and(|o| o.members.iter().any(|om| om.host == m.host)) {
+                    let resolver = &self.resolver;
+                    lookups.push(async move {
+                        (
+                            (pool.name.clone(), m.host.clone()),
+                            Member::from_config(m, pool, resolver).await,
+                        )
+                    });
+                }
+            }
+        }
+        let mut fresh: HashMap<PollerKey, Vec<Member>> =
+            join_all(lookups).await.into_iter().collect();
+
+        let mut to_stop: Vec<PollerKey> = Vec::new();
+        let mut pools_to_stop: Vec<String> = Vec::new();
+        let mut to_start: Vec<PollerKey> = Vec::new();
+        {
+            let mut table = self.cache.lock().unwrap();
+
+            for name in self.pools.keys() {
+                if !new_pools.contains_key(name) {
+                    table.remove(name);
+                    pools_to_stop.push(name.clone());
+                    report.removed_pools.push(name.clone());
+                }
+            }
+
+            for (name, pool) in &new_pools {
+                let old = self.pools.get(name);
+                let entry = table
+                    .entry(name.clone())
+                    .or_insert_with(|| PoolHealth::new(Vec::new()));
+                let mut previous = std::mem::take(&mut entry.members);
+
+                let mut members = Vec::new();
+                for m in &pool.members {
+                    let key = (name.clone(), m.host.clone());
+                    // A host has several members if the pool tracks every resolved address
+                    let (kept, rest): (Vec<Member>, Vec<Member>) = previous
+                        .into_iter()
+                        .partition(|pm| !pm.is_fallback() && pm.host == m.host);
+                    previous = rest;
+                    if kept.is_empty() || fresh.contains_key(&key) {
+                        if let Some(new) = fresh.remove(&key) {
+                            members.extend(new);
+                        }
+                        if old.is_some() {
+                            report
+                                .added_members
+                                .entry(name.clone())
+                                .or_default()
+                                .push(m.host.clone());
+                        }
+                        to_start.push(key);
+                    } else {
+                        for mut member in kept {
+                            member.weight = m.weight;
+                            member.priority = m.priority;
+                            members.push(member);
+                        }
+                    }
+                }
+                members.extend(pool.fallback_members());
+                entry.members = members;
+                entry.configure(pool);
+
+                match old {
+                    None => report.added_pools.push(name.clone()),
+                    Some(old) => {
+                        let removed: Vec<String> = old
+                            .members
+                            .iter()
+                            .filter(|om| !pool.members.iter().any(|m| m.host == om.host))
+                            .map(|om| om.host.clone())
+                            .collect();
+                        to_stop.extend(removed.iter().map(|h| (name.clone(), h.clone())));
+                        if !removed.is_empty() {
+                            report.removed_members.insert(name.clone(), removed);
+                        }
+                        let reconfigured: Vec<String> = pool
+                            .members
+                            .iter()
+                            .filter(|m| {
+                                old.members.iter().any(|om| {
+                                    om.host == m.host
+                                        && (om.weight != m.weight || om.priority != m.priority)
+                                })
+                            })
+                            .map(|m| m.host.clone())
+                            .collect();
+                        if !reconfigured.is_empty() {
+                            report
+                                .reconfigured_members
+                                .insert(name.clone(), reconfigured);
+                        }
+                        if !old.same_lookup(pool) {
+                            report.reconfigured_pools.push(name.clone());
+                        }
+
+                        // Pollers hold on to the config they were started with, so a change to
+                        // how members are probed means restarting every poller in the pool.
+                        if !old.same_probe(pool) {
+                            report.restarted_pools.push(name.clone());
+                            pools_to_stop.push(name.clone());
+                            to_start.extend(
+                                pool.members.iter().map(|m| (name.clone(), m.host.clone())),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(s) = self.snapshot.take() {
+            info!("Restoring saved health state");
+            persist::restore(&self.cache, &s);
+        }
+
+        for name in &report.removed_pools {
+            self.metrics.remove_pool(name);
+        }
+        for (name, hosts) in &report.removed_members {
+            for host in hosts {
+                self.metrics.remove_member(name, host);
+            }
+        }
+        for name in pools_to_stop {
+            info!("Stopping pollers for {name}");
+            self.pollers.stop_pool(&name);
+        }
+        for key in to_stop {
+            self.stop_poller(&key);
+        }
+        std::mem::swap(&mut self.pools, &mut new_pools);
+        for key in to_start {
+            self.start_poller(key);
+        }
+
+        report.added_pools.sort();
+        report.removed_pools.sort();
+        report.restarted_pools.sort();
+        report.reconfigured_pools.sort();
+        report
+    }
+
+    fn start_poller(&mut self, key: PollerKey) {
+        let pool = match self.pools.get(&key.0) {
+            Some(p) => Arc::clone(p),
+            None => return,
+        };
+        let token = self.pollers.token(&key.0);
+        let cache = Arc::clone(&self.cache);
+        let host = key.1.clone();
+        let metrics = self.metrics.clone();
+        let events = self.events.clone();
+        let resolver = self.resolver.clone();
+        let handle = match pool.poll_type {
+            PollType::HTTP => self.join_set.spawn(healthcheck::http_poller(
+                pool,
+                host,
+                cache,
+                metrics,
+                events,
+                resolver,
+                token.clone(),
+            )),
+            PollType::TCP => self.join_set.spawn(healthcheck::tcp_poller(
+                pool,
+                host,
+                cache,
+                metrics,
+                events,
+                resolver,
+                token.clone(),
+            )),
+        };
+        self.tasks.insert(handle.id(), key.clone());
+        // Replaces, and stops, any poller already running for the member
+        self.pollers.insert(key, token, handle.id());
+    }
+
+    fn stop_poller(&mut self, key: &PollerKey) {
+        if self.pollers.stop_member(key) {
+            info!("Stopping poller for {}: {}", key.0, key.1);
+        }
+    }
+
+    /// Handle a poller which has ended. Pollers only end on their own if something went wrong,
+    /// so restart them. Pollers which were stopped have already been dealt with.
+    fn poller_exited(&mut self, id: Id) {
+        let key = match self.tasks.remove(&id) {
+            Some(k) => k,
+            None => return,
+        };
+        if self.pollers.is_current(&key, id) {
+            warn!("Poller for {}: {} exited. Restarting it.", key.0, key.1);
+            self.start_poller(key);
+        }
+    }
+
+    /// Re-read the config file and apply it
+    pub async fn reload(&mut self, path: &Path) -> Result<ReloadReport, String> {
+        let conf = match read_config(path) {
+            Ok(c) => c,
+            Err(e) => {
+                let error = e.to_string();
+                self.events.send(Event::ReloadFailed {
+                    error: error.clone(),
+                });
+                return Err(error);
+            }
+        };
+        let report = self.apply(conf.pools).await;
+        info!("Config reloaded: {:?}", report);
+        self.events.send(Event::Reload {
+            report: report.clone(),
+        });
+        Ok(report)
+    }
+
+    /// Apply a change made through the API on top of the running pool config, and write it
+    /// back to the config file if enabled. The change is lost on the next reload otherwise.
+    pub async fn change(&mut self, change: Change) -> Result<ReloadReport, ChangeError> {
+        let mut pools: Vec<Pool> = self.pools.values().map(|p| Pool::clone(p)).collect();
+        let changed = match &change {
+            Change::PutPool(pool) => pool.name.clone(),
+            Change::DeletePool(name) => name.clone(),
+            Change::PutMember { pool, .. } | Change::DeleteMember { pool, .. } => pool.clone(),
+        };
+        match change {
+            Change::PutPool(pool) => match pools.iter_mut().find(|p| p.name == pool.name) {
+                Some(p) => *p = pool,
+                None => pools.push(pool),
+            },
+            Change::DeletePool(name) => {
+                let count = pools.len();
+                pools.retain(|p| p.name != name);
+                if pools.len() == count {
+                    return Err(ChangeError::PoolNotFound);
+                }
+            }
+            Change::PutMember { pool, member } => {
+                let pool = find_pool(&mut pools, &pool)?;
+                match pool.members.iter_mut().find(|m| m.host == member.host) {
+                    Some(m) => *m = member,
+                    None => pool.members.push(member),
+                }
+            }
+            Change::DeleteMember { pool, host } => {
+                let pool = find_pool(&mut pools, &pool)?;
+                let count = pool.members.len();
+                pool.members.retain(|m| m.host != host);
+                if pool.members.len() == count {
+                    return Err(ChangeError::MemberNotFound);
+                }
+            }
+        }
+        validate_pools(&pools).map_err(|e| ChangeError::Invalid(e.to_string()))?;
+
+        let report = self.apply(pools.clone()).await;
+        info!("Pools changed through the API: {:?}", report);
+        self.events.send(Event::Change {
+            report: report.clone(),
+        });
+        if let Some(path) = &self.write_back {
+            // Only the changed pool is written, so the rest of the file stays as it was
+            let pool = pools.iter().find(|p| p.name == changed);
+            write_pool(path, &changed, pool).map_err(|e| {
+                error!("Failed to write pool changes to {}: {e}", path.display());
+                ChangeError::WriteFailed(e.to_string())
+            })?;
+        }
+        Ok(report)
+    }
+
+    /// Handle for sending requests from other tasks, and the receiver to pass to `run`
+    pub fn channel() -> (SupervisorHandle, mpsc::Receiver<Request>) {
+        let (tx, rx) = mpsc::channel(8);
+        (SupervisorHandle(tx), rx)
+    }
+
+    /// Long lived task which serves reload and change requests and restarts failed pollers.
+    /// Returns once `shutdown` is cancelled and every poller has been stopped.
+    pub async fn run(
+        mut self,
+        config_path: PathBuf,
+        mut requests: mpsc::Receiver<Request>,
+        shutdown: CancellationToken,
+    ) {
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                Some(request) = requests.recv() => match request {
+                    Request::Reload(reply) => {
+                        let result = self.reload(&config_path).await;
+                        if let Err(e) = &result {
+                            error!("Config reload failed, keeping the running config: {e}");
+                        }
+                        let _ = reply.send(result);
+                    }
+                    Request::Change(change, reply) => {
+                        let _ = reply.send(self.change(change).await);
+                    }
+                },
+                Some(res) = self.join_set.join_next_with_id(), if !self.join_set.is_empty() => {
+                    match res {
+                        Ok((id, _)) => self.poller_exited(id),
+                        Err(e) => self.poller_exited(e.id()),
+                    }
+                }
+            }
+        }
+
+        info!("Stopping {} pollers", self.pollers.len());
+        self.pollers.stop_all();
+        while self.join_set.join_next().await.is_some() {}
+    }
+}
+
+/// The pool named `name`, for changing one of its members
+fn find_pool<'a>(pools: &'a mut [Pool], name: &str) -> Result<&'a mut Pool, ChangeError> {
+    pools
+        .iter_mut()
+        .find(|p| p.name == name)
+        .ok_or(ChangeError::PoolNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::healthcheck::Health;
+    use crate::test_util::pool;
+    use std::sync::Mutex;
+
+    fn resolver() -> Resolver {
+        Resolver::new(&Default::default())
+    }
+
+    fn hosts(cache: &HealthTable, name: &str) -> Vec<String> {
+        cache.lock().unwrap()[name]
+            .members
+            .iter()
+            .map(|m| m.host.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reload_diffs_pools_and_members() {
+        let cache: HealthTable = Arc::new(Mutex::new(HashMap::new()));
+        let mut sup = Supervisor::new(
+            Arc::clone(&cache),
+            Metrics::new(),
+            Events::new(),
+            None,
+            resolver(),
+        );
+
+        let report = sup
+            .apply(vec![
+                pool("a", 8080, &["127.0.0.2", "127.0.0.3"]),
+                pool("b", 8080, &["127.0.0.4"]),
+            ])
+            .await;
+        assert_eq!(report.added_pools, vec!["a", "b"]);
+        assert_eq!(sup.pollers.len(), 3);
+
+        cache.lock().unwrap().get_mut("a").unwrap().members[0].health = Health::Down;
+
+        let report = sup
+            .apply(vec![
+                pool("a", 8080, &["127.0.0.2", "127.0.0.5"]),
+                pool("c", 8080, &["127.0.0.6"]),
+            ])
+            .await;
+        assert_eq!(report.added_pools, vec!["c"]);
+        assert_eq!(report.removed_pools, vec!["b"]);
+        assert!(report.restarted_pools.is_empty());
+        assert_eq!(report.added_members["a"], vec!["127.0.0.5"]);
+        assert_eq!(report.removed_members["a"], vec!["127.0.0.3"]);
+
+        assert_eq!(hosts(&cache, "a"), vec!["127.0.0.2", "127.0.0.5"]);
+        assert!(!cache.lock().unwrap().contains_key("b"));
+        // Unchanged members keep their health
+        assert_eq!(cache.lock().unwrap()["a"].members[0].health, Health::Down);
+
+        assert_eq!(sup.pollers.len(), 3);
+        for key in [("a", "127.0.0.2"), ("a", "127.0.0.5"), ("c", "127.0.0.6")] {
+            assert!(sup.pollers.task(&(key.0.into(), key.1.into())).is_some());
+        }
+        assert!(report.reconfigured_pools.is_empty());
+        assert!(report.reconfigured_members.is_empty());
+
+        // Settings which only change how lookups are answered are reported without restarting
+        let mut a = pool("a", 8080, &[]);
+        a.members = vec![
+            serde_json::from_value(serde_json::json!({"host": "127.0.0.2", "weight": 3})).unwrap(),
+            serde_json::from_value(serde_json::json!("127.0.0.5")).unwrap(),
+        ];
+        a.min_active_members = 2;
+        let mut c = pool("c", 8080, &["127.0.0.6"]);
+        c.fallback_ip = Some([127, 0, 0, 9].into());
+        let report = sup.apply(vec![a, c]).await;
+        assert_eq!(report.reconfigured_pools, vec!["a", "c"]);
+        assert_eq!(report.reconfigured_members["a"], vec!["127.0.0.2"]);
+        assert!(report.restarted_pools.is_empty());
+        assert_eq!(cache.lock().unwrap()["a"].members[0].weight, 3);
+    }
+
+    #[tokio::test]
+    async fn probe_changes_restart_pollers() {
+        let cache: HealthTable = Arc::new(Mutex::new(HashMap::new()));
+        let mut sup = Supervisor::new(
+            Arc::clone(&cache),
+            Metrics::new(),
+            Events::new(),
+            None,
+            resolver(),
+        );
+
+        sup.apply(vec![pool("a", 8080, &["127.0.0.2"])]).await;
+        let before = sup.pollers.task(&("a".into(), "127.0.0.2".into()));
+
+        let report = sup.apply(vec![pool("a", 8080, &["127.0.0.2"])]).await;
+        assert_eq!(report, ReloadReport::default());
+        assert_eq!(sup.pollers.task(&("a".into(), "127.0.0.2".into())), before);
+
+        let report = sup.apply(vec![pool("a", 9090, &["127.0.0.2"])]).await;
+        assert_eq!(report.restarted_pools, vec!["a"]);
+        assert_ne!(sup.pollers.task(&("a".into(), "127.0.0.2".into())), before);
+    }
+
+    #[tokio::test]
+    async fn api_changes_update_pools_and_pollers() {
+        let cache: HealthTable = Arc::new(Mutex::new(HashMap::new()));
+        let mut sup = Supervisor::new(
+            Arc::clone(&cache),
+            Metrics::new(),
+            Events::new(),
+            None,
+            resolver(),
+        );
+        sup.apply(vec![pool("a", 8080, &["127.0.0.2"])]).await;
+
+        let member = |host: &str| serde_json::from_value(serde_json::json!(host)).unwrap();
+        let report = sup
+            .change(Change::PutMember {
+                pool: "a".into(),
+                member: member("127.0.0.3"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.added_members["a"], vec!["127.0.0.3"]);
+        assert_eq!(hosts(&cache, "a"), vec!["127.0.0.2", "127.0.0.3"]);
+        assert_eq!(sup.pollers.len(), 2);
+
+        let report = sup
+            .change(Change::PutPool(pool("b", 8080, &["127.0.0.4"])))
+            .await
+            .unwrap();
+        assert_eq!(report.added_pools, vec!["b"]);
+        assert_eq!(sup.pollers.len(), 3);
+
+        // Invalid changes and unknown names leave the running config alone
+        let mut broken = pool("a", 8080, &["127.0.0.2"]);
+        broken.interval = 0;
+        assert!(matches!(
+            sup.change(Change::PutPool(broken)).await,
+            Err(ChangeError::Invalid(_))
+        ));
+        assert_eq!(
+            sup.change(Change::DeleteMember {
+                pool: "a".into(),
+                host: "127.0.0.9".into()
+            })
+            .await,
+            Err(ChangeError::MemberNotFound)
+        );
+        assert_eq!(
+            sup.change(Change::DeletePool("c".into())).await,
+            Err(ChangeError::PoolNotFound)
+        );
+        assert_eq!(sup.pollers.len(), 3);
+
+        sup.change(Change::DeleteMember {
+            pool: "a".into(),
+            host: "127.0.0.2".into(),
+        })
+        .await
+        .unwrap();
+        let report = sup.change(Change::DeletePool("b".into())).await.unwrap();
+        assert_eq!(report.removed_pools, vec!["b"]);
+        assert_eq!(hosts(&cache, "a"), vec!["127.0.0.3"]);
+        assert!(!cache.lock().unwrap().contains_key("b"));
+        assert_eq!(sup.pollers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn file_changes_trigger_one_reload() {
+        let path =
+            std::env::temp_dir().join(format!("health_checker_watch_{}.json", std::process::id()));
+        fs::write(&path, "{}").unwrap();
+
+        let (reloader, mut requests) = Supervisor::channel();
+        let watcher = tokio::spawn(reload_on_change(path.clone(), reloader));
+        time::sleep(WATCH_INTERVAL * 2).await;
+
+        // Several quick writes are applied as a single reload
+        for i in 0..3 {
+            fs::write(&path, format!("{{\"pools\": []{}}}", " ".repeat(i))).unwrap();
+            time::sleep(WATCH_INTERVAL).await;
+        }
+        let request = time::timeout(WATCH_DEBOUNCE * 3, requests.recv())
+            .await
+            .expect("no reload after the file changed")
+            .unwrap();
+        match request {
+            Request::Reload(reply) => {
+                let _ = reply.send(Ok(ReloadReport::default()));
+            }
+            Request::Change(..) => panic!("expected a reload"),
+        }
+        assert!(time::timeout(WATCH_DEBOUNCE * 2, requests.recv())
+            .await
+            .is_err());
+
+        watcher.abort();
+        fs::remove_file(&path).unwrap();
+    }
+}
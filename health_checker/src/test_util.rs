@@ -0,0 +1,52 @@
+// Copyright 2025 Allyn L. Bottorff
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pools, members and health tables shared by the unit tests
+
+use crate::healthcheck::{
+    Health, HealthTable, Member, MemberConfig, Pool, PoolHealth, DEFAULT_WEIGHT,
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// A polled member for `host`, with the default weight and priority
+pub fn member(host: &str, ip: impl Into<IpAddr>, health: Health) -> Member {
+    let config = MemberConfig {
+        host: host.into(),
+        weight: DEFAULT_WEIGHT,
+        priority: 0,
+    };
+    Member::new(&config, health, ip.into())
+}
+
+/// A health table with a single pool
+pub fn table(name: &str, members: Vec<Member>) -> HealthTable {
+    Arc::new(Mutex::new(HashMap::from([(
+        name.to_string(),
+        PoolHealth::new(members),
+    )])))
+}
+
+/// A TCP pool polled every 10 minutes, so tests are done long before a second probe
+pub fn pool(name: &str, port: u16, members: &[&str]) -> Pool {
+    serde_json::from_value(serde_json::json!({
+        "name": name,
+        "port": port,
+        "interval": 600,
+        "members": members,
+        "poll_type": "TCP",
+    }))
+    .unwrap()
+}